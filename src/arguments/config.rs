@@ -0,0 +1,50 @@
+use std::{env, fs, path::PathBuf};
+
+
+// Default arguments are read from $PSCAN_CONFIG_PATH, or ~/.pscanrc if that
+// isn't set. A missing ~/.pscanrc is fine, a missing file that was asked
+// for explicitly is not.
+pub fn load_config_args() -> Result<Vec<String>, String> {
+
+    let (config_path, required) = match env::var_os("PSCAN_CONFIG_PATH") {
+        Some(p) => (PathBuf::from(p), true),
+        None => match env::var_os("HOME") {
+            Some(home) => (PathBuf::from(home).join(".pscanrc"), false),
+            None => return Ok(Vec::new())
+        }
+    };
+
+    if !required && !config_path.is_file() {
+        return Ok(Vec::new())
+    }
+
+    match fs::read_to_string(&config_path) {
+        Ok(contents) => Ok(parse_config(&contents)),
+        Err(msg) => Err(
+            format!("Could not read config {}: {}", config_path.display(), msg)
+        )
+    }
+}
+
+// One argument per line. Blank lines and lines starting with # are skipped
+pub fn parse_config(contents: &str) -> Vec<String> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn comments_and_blank_lines_skipped() {
+        let contents = "# Search like ripgrep\n--smart-case\n\n  -C\n  2\n";
+        assert_eq!(parse_config(contents), vec!["--smart-case", "-C", "2"]);
+    }
+}
@@ -1,5 +1,4 @@
-
+pub mod config;
 pub mod parser;
+pub use config::load_config_args;
 pub use parser::parse;
-
-
@@ -1,23 +1,113 @@
 use std::{path::PathBuf, fmt};
 
+use crate::lexer::Region;
+
 
 pub const HELP_TEXT: &str = r#"
 pscan - A grep-like tool with detailed match location and context lines
 
 USAGE:
     pscan [OPTIONS] <PATH> <QUERY> [-I ext1 ext2 ...] [-E ext1 ext2 ...]
+    pscan [OPTIONS] -e PATTERN [-e PATTERN ...] [-f FILE] <PATH>
+    pscan [OPTIONS] --call NAME <PATH>
+    pscan --undo
+    pscan --type-list
 
 POSITIONAL ARGUMENTS:
     PATH                Directory or file to search (required)
-    QUERY               Text or pattern to search for (required)
+    QUERY               Text or pattern to search for (required unless -e or
+                        -f is given)
 
 SEARCH OPTIONS:
     -i                  Perform case-insensitive matching (default is case-sensitive)
+    -S, --smart-case    Match a pattern case-insensitively if it is all 
+                        lowercase, and case-sensitively otherwise
+    --case-sensitive    Match case-sensitively, overriding -i or -S
+    -w, --word-regexp   Only match whole words: the characters either side of a
+                        match can't be letters, digits or underscores
+    -x, --line-regexp   Only match if the whole line matches QUERY
+    -v, --invert-match  Show the lines that don't match QUERY instead. With -c
+                        the non-matching lines are counted
+    -P, --regex         Treat patterns as regular expressions. Supports classes
+                        ([a-z], \d, \w, \s), anchors (^, $, \b), alternation (|),
+                        groups ((...), (?:...), (?P<name>...)) and repetition
+                        (*, +, ?, {n,m})
+    -U, --multiline     Search the whole file at once so matches can span
+                        several lines, e.g. -UP "foo\(\s*bar" finds a call
+                        split over two lines. Every line a match covers is
+                        shown
+    -o, --only-matching Print only the matched text of each match, one per line,
+                        instead of the whole line
+    --capture GROUP     With -P, report capture group GROUP (a number or a
+                        name) of each match instead of the whole match
+    --glob-query        Treat patterns as shell-style wildcards: * matches any
+                        run of characters on the line, ? any one character
+                        and [abc] one of a set ([!abc] for none of them)
+    --fuzzy N           Also match text up to N edits (inserted, deleted or
+                        changed characters) away from the pattern, e.g.
+                        recieve_packet for receive_packet. The closest matches
+                        are highlighted and the distance is shown
+    --code-only         Only match code, not comments or string literals, in
+                        Python, Rust, JS/TS, C-family and shell files. Files
+                        in other languages are skipped
+    --comments-only     Only match inside comments, in the same languages
+    --strings-only      Only match inside string literals, in the same
+                        languages
+    --call NAME         Search for calls to NAME with any arguments instead of
+                        a QUERY, e.g. --call print or --call console.log. The
+                        whole call is matched, up to its closing parenthesis
+                        and over several lines if need be, so with --replace
+                        "" it is removed. Parentheses in strings and comments
+                        are skipped. Only the languages --code-only knows are
+                        searched
+    --notebook-outputs  Also search the outputs of Jupyter notebook cells.
+                        Notebooks (.ipynb) are searched cell by cell, and
+                        matches are shown as e.g. nb.ipynb [cell 7] with line
+                        numbers counted within the cell. They are never
+                        rewritten by --write or --interactive
+    --scope             Show the definition each group of matches is in: the
+                        nearest enclosing def/class, fn/impl, function or
+                        the like, found by the file's extension
+    -e PATTERN          Search for PATTERN. Can be given several times, a line
+                        matches if any of the patterns match
+    -f FILE             Read patterns from FILE, one per line (empty lines are
+                        skipped)
+    --distinct-colors   Highlight each pattern's matches in a different colour
+    --boolean           Treat QUERY as a boolean expression of terms joined by
+                        AND, OR, NOT and parentheses. `a NEAR/n b` needs both
+                        within n lines of each other. Quote terms that contain
+                        spaces, e.g. "password" AND NOT "unit test"
+    --file-scope        Evaluate --boolean queries over the whole file rather
+                        than line by line, showing the lines with terms in
+    --replace TEXT      Preview replacing each match with TEXT. With -P, $1 or
+                        ${name} in TEXT stands for a capture group; $0 is the
+                        whole match and $$ a literal $
+    --write             Write the --replace changes back to the files
+    --interactive       Ask before making each --replace change: [y]es, [n]o,
+                        [a]ll remaining or [q]uit. Each one is shown with its
+                        context lines (-A/-B/-C, 2 by default), and only the
+                        accepted changes are written
+    --undo              Put back the files changed by the last --write or
+                        --interactive run in this directory. Each run is
+                        recorded under .pscan/journal/, and files edited
+                        since are left alone
     -r                  Search directories recursively
+    --max-depth N       Don't search more than N directories deep: 1 searches
+                        only the files directly in PATH. Can be given
+                        without -r
+    --min-depth N       Only search files at least N directories deep, e.g.
+                        2 to skip the files directly in PATH
+    --no-ignore         Search files and directories matched by .gitignore,
+                        .ignore, .pscanignore or .git/info/exclude, which
+                        are skipped by default
+    --hidden            Search hidden files and directories (names starting
+                        with a dot, like .git), which are skipped by default
     -s                  Show parsed arguments before starting search (useful for debugging)
-    -t                  Trims white space from any matching lines
+    --trim              Trims white space from any matching lines
     -b                  Enables binary file reading.
-    -c                  Only shows the number of matches found per file
+    -c                  Only shows the number of matching lines found per file
+    --count-matches     Only shows the total number of matches found per file,
+                        counting every occurrence on a line
 
 CONTEXT CONTROL:
     -A, --after N       Print N lines of trailing context after each match
@@ -26,6 +116,15 @@ CONTEXT CONTROL:
                         (equivalent to -B N -A N)
 
 FILE TYPE FILTERING:
+    -t, --type TYPE     Only search files of TYPE, e.g. py for .py, .pyi and
+                        .pyx files and scripts starting with #!/usr/bin/env
+                        python. Can be given several times
+    -T, --type-not TYPE Skip files of TYPE. Can be given several times
+    --type-list         Show the known file types and exit
+    --type-add NAME:DEF,DEF,...
+                        Define a file type, or add to one. Each DEF is *.ext,
+                        a file name like Makefile or #!interpreter
+                        (e.g. --type-add 'web:*.html,*.css,*.vue')
     -I, --include-file-types ext1 ext2 ...
                         Only search files whose extension is in the list
                         (e.g. -I rs toml yaml)
@@ -33,16 +132,45 @@ FILE TYPE FILTERING:
                         Skip files whose extension is in the list. Will also skip 
                         directories if the directory name is included here.
                         (e.g. -E jpg png gif node_modules)
+    -g, --glob GLOB     Only search files whose path from PATH matches GLOB,
+                        or with a leading ! skip the files and directories
+                        that match. ** matches any number of directories, and
+                        a GLOB without a / matches the file name anywhere.
+                        Can be given several times
+                        (e.g. -g 'src/**/*.py' -g '!**/migrations/**')
 
 EXAMPLES:
     pscan ./src "println!"
     pscan -i -r . "error handling"
+    pscan --max-depth 2 --min-depth 2 packages "version"
     pscan -C 2 Cargo.toml "version"
     pscan -I rs toml -r src "unsafe"
+    pscan -r -t py -T docker . "DEBUG"
+    pscan -r -g 'src/**/*.py' -g '!**/migrations/**' . "print("
+    pscan -P -r . "TODO\(\w+\)"
+    pscan -r -e "print(" -e "breakpoint()" -f banned.txt src
+    pscan -rUP src "if \(.*\)\s*\{\s*\}"
+    pscan -roP logs "TICKET-\d+"
+    pscan -roP --capture url logs "href=\"(?P<url>[^\"]+)\""
+    pscan -r --replace "" --write src "breakpoint()"
+    pscan -r --glob-query src "print(*debug*)"
+    pscan -r --fuzzy 2 src "receive_packet"
+    pscan -r --code-only src "print("
+    pscan -r --call print --replace "" --write src
+    pscan -r --boolean --file-scope src "unsafe AND transmute"
     pscan --before 1 --after 3 logs "ERROR"
 
+CONFIGURATION:
+    Default options can be kept in a config file, read from $PSCAN_CONFIG_PATH
+    or ~/.pscanrc. Put one argument per line; lines starting with # are
+    ignored. Options given on the command line are applied after these, so
+    e.g. a config containing --smart-case can be overridden with -i.
+
 NOTE:
-    Short options can be combined: -ris is equivalent to -r -i -s
+    Short options can be combined: -ris is equivalent to -r -i -s. Unknown
+    options are an error
+    -t used to trim lines; it is now --type, and trimming is --trim
+    -e gives a pattern to search for; regular expressions are -P/--regex, not -e
     Extensions for -I/-E should be given without leading dot
 "#; 
 
@@ -51,21 +179,54 @@ pub struct ParsedArgs<'a> {
     
     pub path: PathBuf,
     pub query: &'a str,
+    pub patterns: Vec<&'a str>,
+    pub pattern_files: Vec<&'a str>,
     
     pub recursive: bool,
+    pub max_depth: Option<usize>,
+    pub min_depth: usize,
+    pub no_ignore: bool,
+    pub hidden: bool,
     pub case_sensitive: bool,
+    pub smart_case: bool,
+    pub regex: bool,
+    pub glob_query: bool,
+    pub only_matching: bool,
+    pub capture: Option<&'a str>,
+    pub replace: Option<&'a str>,
+    pub write: bool,
+    pub interactive: bool,
+    pub word_regexp: bool,
+    pub line_regexp: bool,
+    pub invert_match: bool,
+    pub multiline: bool,
+    pub fuzzy: Option<usize>,
+    pub only_region: Option<Region>,
+    pub scope: bool,
+    pub call: Option<&'a str>,
+    pub notebook_outputs: bool,
+    pub distinct_colors: bool,
+    pub boolean: bool,
+    pub file_scope: bool,
     pub show_args: bool,
     pub trim: bool,
     pub binary_ok: bool,
     pub count_only: bool,
+    pub count_matches: bool,
     
     pub context_before: usize,  
     pub context_after: usize,
 
     pub include_file_types: Option<Vec<&'a str>>,
+    pub globs: Vec<&'a str>,
     pub exclude_file_types: Option<Vec<&'a str>>,
+    pub types: Vec<&'a str>,
+    pub types_not: Vec<&'a str>,
+    pub type_adds: Vec<&'a str>,
 
-    pub help: bool
+    pub help: bool,
+    pub undo: bool,
+    pub type_list: bool
 
 }
 
@@ -76,16 +237,80 @@ impl<'a> fmt::Display for ParsedArgs<'a> {
             self.path.display())?;
         write!(f, "\n  \x1b[33mquery:             \x1b[0m {}",
             self.query)?;
+        if !self.patterns.is_empty() {
+            write!(f, "\n  \x1b[33mpatterns:          \x1b[0m {:?}", 
+                self.patterns)?;
+        };
+        if !self.pattern_files.is_empty() {
+            write!(f, "\n  \x1b[33mpattern_files:     \x1b[0m {:?}", 
+                self.pattern_files)?;
+        };
         write!(f, "\n  \x1b[33mrecursive:     \x1b[0m     {}",
             self.recursive)?;
+        if let Some(n) = self.max_depth {
+            write!(f, "\n  \x1b[33mmax_depth:     \x1b[0m     {}", n)?;
+        };
+        write!(f, "\n  \x1b[33mmin_depth:     \x1b[0m     {}",
+            self.min_depth)?;
+        write!(f, "\n  \x1b[33mno_ignore:     \x1b[0m     {}",
+            self.no_ignore)?;
+        write!(f, "\n  \x1b[33mhidden:        \x1b[0m     {}",
+            self.hidden)?;
         write!(f, "\n  \x1b[33mtrim:          \x1b[0m     {}",
             self.trim)?;
         write!(f, "\n  \x1b[33mbinary_ok:     \x1b[0m     {}",
             self.binary_ok)?;
         write!(f, "\n  \x1b[33mcase_sensitive:\x1b[0m     {}", 
             self.case_sensitive)?;
+        write!(f, "\n  \x1b[33msmart_case:    \x1b[0m     {}", 
+            self.smart_case)?;
+        write!(f, "\n  \x1b[33mregex:         \x1b[0m     {}", 
+            self.regex)?;
+        write!(f, "\n  \x1b[33mglob_query:    \x1b[0m     {}", 
+            self.glob_query)?;
+        write!(f, "\n  \x1b[33monly_matching: \x1b[0m     {}", 
+            self.only_matching)?;
+        if let Some(group) = self.capture {
+            write!(f, "\n  \x1b[33mcapture:       \x1b[0m     {}", group)?;
+        };
+        if let Some(text) = self.replace {
+            write!(f, "\n  \x1b[33mreplace:       \x1b[0m     {:?}", text)?;
+        };
+        write!(f, "\n  \x1b[33mwrite:         \x1b[0m     {}", 
+            self.write)?;
+        write!(f, "\n  \x1b[33minteractive:   \x1b[0m     {}", 
+            self.interactive)?;
+        write!(f, "\n  \x1b[33mword_regexp:   \x1b[0m     {}", 
+            self.word_regexp)?;
+        write!(f, "\n  \x1b[33mline_regexp:   \x1b[0m     {}", 
+            self.line_regexp)?;
+        write!(f, "\n  \x1b[33minvert_match:  \x1b[0m     {}", 
+            self.invert_match)?;
+        write!(f, "\n  \x1b[33mmultiline:     \x1b[0m     {}", 
+            self.multiline)?;
+        write!(f, "\n  \x1b[33mdistinct_colors:\x1b[0m    {}", 
+            self.distinct_colors)?;
+        if let Some(n) = self.fuzzy {
+            write!(f, "\n  \x1b[33mfuzzy:         \x1b[0m     {}", n)?;
+        };
+        write!(f, "\n  \x1b[33mscope:         \x1b[0m     {}", 
+            self.scope)?;
+        write!(f, "\n  \x1b[33mnotebook_outputs:\x1b[0m   {}", 
+            self.notebook_outputs)?;
+        if let Some(name) = self.call {
+            write!(f, "\n  \x1b[33mcall:          \x1b[0m     {}", name)?;
+        };
+        if let Some(region) = self.only_region {
+            write!(f, "\n  \x1b[33monly_region:   \x1b[0m     {:?}", region)?;
+        };
+        write!(f, "\n  \x1b[33mboolean:       \x1b[0m     {}", 
+            self.boolean)?;
+        write!(f, "\n  \x1b[33mfile_scope:    \x1b[0m     {}", 
+            self.file_scope)?;
         write!(f, "\n  \x1b[33mcount_only:    \x1b[0m     {}", 
             self.count_only)?;
+        write!(f, "\n  \x1b[33mcount_matches: \x1b[0m     {}", 
+            self.count_matches)?;
         write!(f, "\n  \x1b[33mcontext_before:\x1b[0m     {}",
             self.context_before)?;
         write!(f, "\n  \x1b[33mcontext_after:\x1b[0m      {}",
@@ -96,6 +321,18 @@ impl<'a> fmt::Display for ParsedArgs<'a> {
         if let Some(t) = &self.exclude_file_types {
             write!(f, "\n  \x1b[33mexclude_file_types:\x1b[0m {:?}", t)?;
         };
+        if !self.globs.is_empty() {
+            write!(f, "\n  \x1b[33mglobs:             \x1b[0m {:?}", self.globs)?;
+        };
+        if !self.types.is_empty() {
+            write!(f, "\n  \x1b[33mtypes:             \x1b[0m {:?}", self.types)?;
+        };
+        if !self.types_not.is_empty() {
+            write!(f, "\n  \x1b[33mtypes_not:         \x1b[0m {:?}", self.types_not)?;
+        };
+        if !self.type_adds.is_empty() {
+            write!(f, "\n  \x1b[33mtype_adds:         \x1b[0m {:?}", self.type_adds)?;
+        };
         write!(f, "\n  \x1b[33mshow_args:\x1b[0m          {}",
             &self.show_args)?;
         write!(f, "\n\x1b[1m}}\x1b[0m")
@@ -104,51 +341,77 @@ impl<'a> fmt::Display for ParsedArgs<'a> {
 
 impl<'a> ParsedArgs<'a> {
     
-    fn new(args: &'a [String]) -> Result<Self, &'static str> {
-
-        if args.len() == 1 && args.contains(&"--help".to_string()) {
-            Ok(ParsedArgs {
-                path: PathBuf::from(""),
-                query: "",
-                recursive: false,
-                case_sensitive: false,
-                show_args: false,
-                trim: false, 
-                binary_ok: false,
-                count_only: false,
-                context_before: 0, 
-                context_after: 0,
-                include_file_types: None,
-                exclude_file_types: None,
-                help: true 
-            })
+    // The first `config_len` arguments are the ones from the config file
+    fn new(args: &'a [String], config_len: usize) -> Result<Self, &'static str> {
+
+        if args.is_empty() {
+            Err("Must pass a search path and phrase, in that order")
         }
-        else if args.len() >= 2 {
+        else {
             
             let mut path: PathBuf = PathBuf::from("");
             let mut query: &'a str = "";
+            let mut patterns: Vec<&'a str> = Vec::new();
+            let mut pattern_files: Vec<&'a str> = Vec::new();
 
             let mut recursive: bool = false;
+            let mut max_depth: Option<usize> = None;
+            let mut min_depth: usize = 0;
+            let mut no_ignore: bool = false;
+            let mut hidden: bool = false;
             let mut show_args: bool = false;
             let mut trim: bool = false;
             let mut binary_ok: bool = false;
             let mut count_only: bool = false;
+            let mut count_matches: bool = false;
             let mut case_sensitive: bool = true;
+            let mut smart_case: bool = false;
+            let mut regex: bool = false;
+            let mut glob_query: bool = false;
+            let mut only_matching: bool = false;
+            let mut capture: Option<&'a str> = None;
+            let mut replace: Option<&'a str> = None;
+            let mut write: bool = false;
+            let mut interactive: bool = false;
+            let mut word_regexp: bool = false;
+            let mut line_regexp: bool = false;
+            let mut invert_match: bool = false;
+            let mut multiline: bool = false;
+            let mut fuzzy: Option<usize> = None;
+            let mut only_regions: Vec<Region> = Vec::new();
+            let mut scope: bool = false;
+            let mut call: Option<&'a str> = None;
+            let mut notebook_outputs: bool = false;
+            let mut distinct_colors: bool = false;
+            let mut boolean: bool = false;
+            let mut file_scope: bool = false;
 
             let mut context_before: usize = 0;
             let mut context_after: usize = 0;
             
             let mut include_file_types: Option<Vec<&'a str>> = None;
+            let mut globs: Vec<&'a str> = Vec::new();
             let mut exclude_file_types: Option<Vec<&'a str>> = None;
+            let mut types: Vec<&'a str> = Vec::new();
+            let mut types_not: Vec<&'a str> = Vec::new();
+            let mut type_adds: Vec<&'a str> = Vec::new();
 
             let mut key: char = '!';
 
             let mut help: bool = false;
+            let mut undo: bool = false;
+            let mut type_list: bool = false;
 
             let mut iter_count = 0;
             while iter_count < args.len() {
               
                 let argument = &args[iter_count];
+
+                // A -I or -E at the end of the config file doesn't take the
+                // command line's PATH and QUERY as extensions
+                if iter_count == config_len {
+                    key = '!';
+                }
                 
                 if argument.starts_with('-') {
                  
@@ -159,7 +422,12 @@ impl<'a> ParsedArgs<'a> {
                         // Context options
                         "-A"      | "-B"       | "-C" | 
                         "--after" | "--before" | "--context" => { 
-                            let n = args[iter_count + 1].parse::<usize>();
+                            let n = match args.get(iter_count + 1) {
+                                Some(v) => v.parse::<usize>(),
+                                None => return Err(
+                                    "ArgumentError: Missing number of context lines"
+                                )
+                            };
                             if let Ok(d) = n {
                                 match arg_str { 
                                     "-A" | "--after" => { 
@@ -173,7 +441,111 @@ impl<'a> ParsedArgs<'a> {
                                         context_before = d;
                                     }
                                 }
+                                iter_count += 1;
+                            }
+                        },
+
+                        "--capture" => {
+                            match args.get(iter_count + 1) {
+                                Some(v) => capture = Some(v.as_str()),
+                                None => return Err(
+                                    "ArgumentError: --capture needs a group number or name"
+                                )
+                            }
+                            iter_count += 1;
+                        },
+                        "--replace" => {
+                            match args.get(iter_count + 1) {
+                                Some(v) => replace = Some(v.as_str()),
+                                None => return Err(
+                                    "ArgumentError: --replace needs the replacement text"
+                                )
+                            }
+                            iter_count += 1;
+                        },
+                        "--write" => { write = true },
+                        "--interactive" => { interactive = true },
+                        "--code-only" => { only_regions.push(Region::Code) },
+                        "--comments-only" => { only_regions.push(Region::Comment) },
+                        "--strings-only" => { only_regions.push(Region::String) },
+                        "--scope" => { scope = true },
+                        "--no-ignore" => { no_ignore = true },
+                        "--hidden" => { hidden = true },
+                        "--notebook-outputs" => { notebook_outputs = true },
+                        "--trim" => { trim = true },
+
+                        // Options taking a single value
+                        "-e" | "-f" => {
+                            let value = match args.get(iter_count + 1) {
+                                Some(v) => v.as_str(),
+                                None => return Err(
+                                    "ArgumentError: -e and -f need a value"
+                                )
+                            };
+                            match arg_str {
+                                "-e" => patterns.push(value),
+                                _ => pattern_files.push(value)
+                            }
+                            iter_count += 1;
+                        },
+
+                        "-g" | "--glob" => {
+                            match args.get(iter_count + 1) {
+                                Some(v) => globs.push(v.as_str()),
+                                None => return Err(
+                                    "ArgumentError: -g/--glob needs a glob"
+                                )
                             }
+                            iter_count += 1;
+                        },
+
+                        "-t" | "--type" | "-T" | "--type-not" | "--type-add" => {
+                            let value = match args.get(iter_count + 1) {
+                                Some(v) => v.as_str(),
+                                None => return Err(
+                                    "ArgumentError: -t, -T and --type-add need a file type"
+                                )
+                            };
+                            match arg_str {
+                                "-t" | "--type" => types.push(value),
+                                "-T" | "--type-not" => types_not.push(value),
+                                _ => type_adds.push(value)
+                            }
+                            iter_count += 1;
+                        },
+
+                        "--max-depth" | "--min-depth" => {
+                            let n = match args.get(iter_count + 1).map(|v| v.parse::<usize>()) {
+                                Some(Ok(n)) => n,
+                                _ => return Err(
+                                    "ArgumentError: --max-depth and --min-depth need a number"
+                                )
+                            };
+                            match arg_str {
+                                "--max-depth" => max_depth = Some(n),
+                                _ => min_depth = n
+                            }
+                            iter_count += 1;
+                        },
+
+                        "--call" => {
+                            match args.get(iter_count + 1) {
+                                Some(v) if !v.is_empty() => call = Some(v.as_str()),
+                                _ => return Err(
+                                    "ArgumentError: --call needs the name of a function"
+                                )
+                            }
+                            iter_count += 1;
+                        },
+
+                        "--fuzzy" => {
+                            match args.get(iter_count + 1).map(|v| v.parse::<usize>()) {
+                                Some(Ok(n)) => fuzzy = Some(n),
+                                _ => return Err(
+                                    "ArgumentError: --fuzzy needs a number of edits"
+                                )
+                            }
+                            iter_count += 1;
                         },
 
                         "-E" | "--exclude-file-types" => {
@@ -189,18 +561,60 @@ impl<'a> ParsedArgs<'a> {
                             key = 'I';
                         },
                         
+                        "--regex" => { regex = true },
+                        "--glob-query" => { glob_query = true },
+                        "--only-matching" => { only_matching = true },
+                        "--distinct-colors" => { distinct_colors = true },
+                        "--boolean" => { boolean = true },
+                        "--file-scope" => { file_scope = true },
+
+                        // Case options: the last one given wins
+                        "--smart-case" => { smart_case = true },
+                        "--case-sensitive" => { 
+                            case_sensitive = true;
+                            smart_case = false;
+                        },
+                        "--count-matches" => { count_matches = true },
+                        "--word-regexp" => { word_regexp = true },
+                        "--line-regexp" => { line_regexp = true },
+                        "--invert-match" => { invert_match = true },
+                        "--multiline" => { multiline = true },
+
                         // Print the help menu and exit
                         "--help" => { help = true; break } 
+                        "--undo" => { undo = true },
+                        "--type-list" => { type_list = true },
+
+                        _ if arg_str.starts_with("--") => {
+                            return Err("ArgumentError: Unknown option, see --help")
+                        },
 
                         // Single param short options: Ex: -ri
                         _ => {
-                            for param in argument.chars() {
-                                if param == 'i' { case_sensitive = false }
+                            for param in argument.chars().skip(1) {
+                                if param == 'i' { 
+                                    case_sensitive = false;
+                                    smart_case = false;
+                                }
+                                else if param == 'S' { smart_case = true }
                                 else if param == 'r' { recursive = true }
                                 else if param == 's' { show_args = true }
-                                else if param == 't' { trim = true }
                                 else if param == 'b' { binary_ok = true }
                                 else if param == 'c' { count_only = true }
+                                else if param == 'P' { regex = true }
+                                else if param == 'w' { word_regexp = true }
+                                else if param == 'x' { line_regexp = true }
+                                else if param == 'v' { invert_match = true }
+                                else if param == 'U' { multiline = true }
+                                else if param == 'o' { only_matching = true }
+                                else if param == 't' {
+                                    return Err(
+                                        "ArgumentError: -t takes a file type and can't be combined with other options. Trimming lines is --trim"
+                                    )
+                                }
+                                else {
+                                    return Err("ArgumentError: Unknown option, see --help")
+                                }
                             }
                         }
                     }
@@ -212,16 +626,15 @@ impl<'a> ParsedArgs<'a> {
                                 vals.push(argument);
                             }
                         }
-                        else if key == 'E' {
-                            if let Some(ref mut vals) = exclude_file_types {
-                                vals.push(argument);
-                            }
+                        else if key == 'E' 
+                            && let Some(ref mut vals) = exclude_file_types {
+                            vals.push(argument);
                         }
                     }
                     else if path.as_os_str().is_empty() { 
                         path = PathBuf::from(argument.as_str()); 
                     }
-                    else if query == "" { 
+                    else if query.is_empty() { 
                         query = argument.as_str() 
                     } 
                 }
@@ -229,39 +642,175 @@ impl<'a> ParsedArgs<'a> {
                 iter_count += 1;
             }
 
-            if path.as_os_str().is_empty() {
+            // Only as options, not as the value of one, like -e --undo
+            if help || type_list || undo {
+                let type_list = type_list && !help;
+                Ok(ParsedArgs::mode(help, undo && !help && !type_list, type_list, type_adds))
+            }
+            else if path.as_os_str().is_empty() {
                 Err("ArgumentError: Must pass a root path to search")
             }
-            else if query == "" {
+            else if query.is_empty() 
+                && patterns.is_empty() 
+                && pattern_files.is_empty() 
+                && call.is_none() {
                 Err("ArgumentError: Must pass a search query")
             }
+            else if call.is_some() 
+                && (!query.is_empty() || !patterns.is_empty() || !pattern_files.is_empty()) {
+                Err("ArgumentError: --call can't be used with a QUERY, -e or -f")
+            }
+            else if call.is_some() 
+                && (regex || glob_query || fuzzy.is_some() || boolean || interactive) {
+                Err("ArgumentError: --call can't be used with -P, --glob-query, --fuzzy, --boolean or --interactive")
+            }
+            else if (write || interactive) && replace.is_none() {
+                Err("ArgumentError: --write and --interactive need --replace")
+            }
+            else if replace.is_some() && (multiline || invert_match) {
+                Err("ArgumentError: --replace can't be used with -U or -v")
+            }
+            else if scope && trim {
+                Err("ArgumentError: --scope can't be used with --trim")
+            }
+            else if (write || interactive) && trim {
+                Err("ArgumentError: --write and --interactive can't be used with --trim")
+            }
+            else if capture.is_some() && !regex {
+                Err("ArgumentError: --capture needs --regex")
+            }
+            else if glob_query && regex {
+                Err("ArgumentError: --glob-query can't be used with --regex")
+            }
+            else if fuzzy.is_some() && (regex || glob_query) {
+                Err("ArgumentError: --fuzzy can't be used with --regex or --glob-query")
+            }
+            else if only_regions.len() > 1 {
+                Err("ArgumentError: Only one of --code-only, --comments-only and --strings-only can be given")
+            }
+            else if max_depth.is_some_and(|max| min_depth > max) {
+                Err("ArgumentError: --min-depth can't be more than --max-depth")
+            }
+            else if multiline && boolean {
+                Err("ArgumentError: --multiline can't be used with --boolean")
+            }
             else {
+                if interactive && context_before == 0 && context_after == 0 {
+                    context_before = 2;
+                    context_after = 2;
+                }
                 Ok(ParsedArgs {
                     query,
+                    patterns,
+                    pattern_files,
                     path,
                     recursive,
+                    max_depth,
+                    min_depth,
+                    no_ignore,
+                    hidden,
                     case_sensitive, 
+                    smart_case,
+                    regex,
+                    glob_query,
+                    only_matching,
+                    capture,
+                    replace,
+                    write,
+                    interactive,
+                    word_regexp,
+                    line_regexp,
+                    invert_match,
+                    multiline,
+                    fuzzy,
+                    only_region: only_regions.first().copied(),
+                    scope,
+                    call,
+                    notebook_outputs,
+                    distinct_colors,
+                    boolean,
+                    file_scope,
                     show_args,
                     trim,
                     binary_ok,
                     count_only,
+                    count_matches,
                     context_before, 
                     context_after, 
                     include_file_types, 
+                    globs,
                     exclude_file_types,
+                    types,
+                    types_not,
+                    type_adds,
                     help, 
+                    undo: false,
+                    type_list: false
                 }) 
             }
-        
-        } else {
-            Err("Must pass a search path and phrase, in that order")
+        }
+    }
+
+    // For --help, --undo and --type-list, which don't search. --type-list
+    // shows the types added with --type-add too.
+    fn mode(help: bool, undo: bool, type_list: bool, type_adds: Vec<&'a str>) -> Self {
+        ParsedArgs {
+            path: PathBuf::from(""),
+            query: "",
+            patterns: Vec::new(),
+            pattern_files: Vec::new(),
+            recursive: false,
+            max_depth: None,
+            min_depth: 0,
+            no_ignore: false,
+            hidden: false,
+            case_sensitive: false,
+            smart_case: false,
+            regex: false,
+            glob_query: false,
+            only_matching: false,
+            capture: None,
+            replace: None,
+            write: false,
+            interactive: false,
+            word_regexp: false,
+            line_regexp: false,
+            invert_match: false,
+            multiline: false,
+            fuzzy: None,
+            only_region: None,
+            scope: false,
+            call: None,
+            notebook_outputs: false,
+            distinct_colors: false,
+            boolean: false,
+            file_scope: false,
+            show_args: false,
+            trim: false, 
+            binary_ok: false,
+            count_only: false,
+            count_matches: false,
+            context_before: 0, 
+            context_after: 0,
+            include_file_types: None,
+            globs: Vec::new(),
+            exclude_file_types: None,
+            types: Vec::new(),
+            types_not: Vec::new(),
+            type_adds,
+            help,
+            undo,
+            type_list
         }
     }
 }
 
 
-pub fn parse<'a>(args: &'a [String]) -> Result<ParsedArgs<'a>, &'static str> {
-    ParsedArgs::new(&args)
+pub fn parse<'a>(
+    args: &'a [String], 
+    config_len: usize
+) -> Result<ParsedArgs<'a>, &'static str> {
+    ParsedArgs::new(args, config_len)
 }
 
 
@@ -276,7 +825,7 @@ mod tests {
             "./search_path".to_string(),
             "search_query".to_string()
         ];
-        let parsed = match ParsedArgs::new(&args) {
+        let parsed = match ParsedArgs::new(&args, 0) {
             Ok(p) => p,
             Err(_) => panic!("Arg parsing failed")
         };
@@ -295,7 +844,7 @@ mod tests {
             "./search_path".to_string(),
             "search_query".to_string()
         ];
-        let parsed = match ParsedArgs::new(&args) {
+        let parsed = match ParsedArgs::new(&args, 0) {
             Ok(p) => p,
             Err(_) => panic!("Arg parsing failed")
         };
@@ -316,7 +865,7 @@ mod tests {
             "-A".to_string(),
             "3".to_string()
         ];
-        let parsed = match ParsedArgs::new(&args) {
+        let parsed = match ParsedArgs::new(&args, 0) {
             Ok(p) => p,
             Err(_) => panic!("Arg parsing failed")
         };
@@ -336,7 +885,7 @@ mod tests {
             "-B".to_string(),
             "3".to_string()
         ];
-        let parsed = match ParsedArgs::new(&args) {
+        let parsed = match ParsedArgs::new(&args, 0) {
             Ok(p) => p,
             Err(_) => panic!("Arg parsing failed")
         };
@@ -357,7 +906,7 @@ mod tests {
             "-C".to_string(),
             "3".to_string()
         ];
-        let parsed = match ParsedArgs::new(&args) {
+        let parsed = match ParsedArgs::new(&args, 0) {
             Ok(p) => p,
             Err(_) => panic!("Arg parsing failed")
         };
@@ -377,7 +926,7 @@ mod tests {
             "-E".to_string(),
             ".py".to_string()
         ];
-        let parsed = match ParsedArgs::new(&args) {
+        let parsed = match ParsedArgs::new(&args, 0) {
             Ok(p) => p,
             Err(_) => panic!("Arg parsing failed")
         };
@@ -387,7 +936,7 @@ mod tests {
         assert_eq!(parsed.context_after, 0);
         assert!(parsed.include_file_types.is_none());
         assert!(parsed.exclude_file_types.is_some());
-        assert!(parsed.exclude_file_types.unwrap().len() > 0);
+        assert!(!parsed.exclude_file_types.unwrap().is_empty());
     }
 
     #[test]
@@ -398,7 +947,7 @@ mod tests {
             "-I".to_string(),
             ".sh".to_string()
         ];
-        let parsed = match ParsedArgs::new(&args) {
+        let parsed = match ParsedArgs::new(&args, 0) {
             Ok(p) => p,
             Err(_) => panic!("Arg parsing failed")
         };
@@ -407,9 +956,148 @@ mod tests {
         assert_eq!(parsed.context_before, 0); 
         assert_eq!(parsed.context_after, 0);
         assert!(parsed.include_file_types.is_some());
-        assert!(parsed.include_file_types.unwrap().len() > 0);
+        assert!(!parsed.include_file_types.unwrap().is_empty());
         assert!(parsed.exclude_file_types.is_none());
     }
-}
 
+    #[test]
+    fn regex_flag_passed() {
+        let args: Vec<String> = vec![
+            "-rP".to_string(),
+            "./search_path".to_string(),
+            r"print\(.*\)$".to_string()
+        ];
+        let parsed = match ParsedArgs::new(&args, 0) {
+            Ok(p) => p,
+            Err(_) => panic!("Arg parsing failed")
+        };
+        assert!(parsed.recursive); 
+        assert!(parsed.regex); 
+        assert_eq!(parsed.query, r"print\(.*\)$");
+    }
 
+    #[test]
+    fn smart_case_flag_passed() {
+        let lower: Vec<String> = vec![
+            "-S".to_string(),
+            "./search_path".to_string(),
+            "search_query".to_string()
+        ];
+        let upper: Vec<String> = vec![
+            "--smart-case".to_string(),
+            "./search_path".to_string(),
+            "Search_Query".to_string()
+        ];
+        let overridden: Vec<String> = vec![
+            "-S".to_string(),
+            "-i".to_string(),
+            "./search_path".to_string(),
+            "Search_Query".to_string()
+        ];
+        let sensitive: Vec<String> = vec![
+            "-S".to_string(),
+            "--case-sensitive".to_string(),
+            "./search_path".to_string(),
+            "search_query".to_string()
+        ];
+        // Whether the query has uppercase letters is checked per pattern 
+        // when the matcher is built
+        for (args, smart_case, case_sensitive) in [
+            (lower, true, true), 
+            (upper, true, true), 
+            (overridden, false, false),
+            (sensitive, false, true)
+        ] {
+            let parsed = match ParsedArgs::new(&args, 0) {
+                Ok(p) => p,
+                Err(_) => panic!("Arg parsing failed")
+            };
+            assert_eq!(parsed.smart_case, smart_case);
+            assert_eq!(parsed.case_sensitive, case_sensitive);
+        }
+    }
+
+    #[test]
+    fn multiple_patterns_passed() {
+        let args: Vec<String> = vec![
+            "-e".to_string(),
+            "print(".to_string(),
+            "-A".to_string(),
+            "2".to_string(),
+            "-e".to_string(),
+            "breakpoint()".to_string(),
+            "-f".to_string(),
+            "banned.txt".to_string(),
+            "./search_path".to_string()
+        ];
+        let parsed = match ParsedArgs::new(&args, 0) {
+            Ok(p) => p,
+            Err(_) => panic!("Arg parsing failed")
+        };
+        assert_eq!(parsed.path, PathBuf::from("./search_path"));
+        assert_eq!(parsed.query, "");
+        assert_eq!(parsed.patterns, vec!["print(", "breakpoint()"]);
+        assert_eq!(parsed.pattern_files, vec!["banned.txt"]);
+        assert_eq!(parsed.context_after, 2);
+    }
+
+    #[test]
+    fn unknown_options_rejected() {
+        let parse = |flag: &str| {
+            let args: Vec<String> = vec![
+                flag.to_string(),
+                "./search_path".to_string(),
+                "search_query".to_string()
+            ];
+            ParsedArgs::new(&args, 0).map(|p| (p.trim, p.recursive, p.types.len()))
+        };
+        assert_eq!(parse("-ri"), Ok((false, true, 0)));
+        assert_eq!(parse("--trim"), Ok((true, false, 0)));
+        assert!(parse("-rt").is_err());
+        assert!(parse("-rq").is_err());
+        assert!(parse("--recursive").is_err());
+    }
+
+    #[test]
+    fn modes_only_from_options() {
+        let to_args = |args: &[&str]| args.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+
+        let args = to_args(&["-e", "--undo", "--replace", "--type-list", "./search_path"]);
+        let parsed = match ParsedArgs::new(&args, 0) {
+            Ok(p) => p,
+            Err(msg) => panic!("{}", msg)
+        };
+        assert!(!parsed.undo && !parsed.type_list && !parsed.help);
+        assert_eq!(parsed.patterns, vec!["--undo"]);
+        assert_eq!(parsed.replace, Some("--type-list"));
+
+        let args = to_args(&["--type-add", "web:*.vue", "--type-list"]);
+        let parsed = match ParsedArgs::new(&args, 0) {
+            Ok(p) => p,
+            Err(msg) => panic!("{}", msg)
+        };
+        assert!(parsed.type_list && !parsed.undo);
+        assert_eq!(parsed.type_adds, vec!["web:*.vue"]);
+
+        let args = to_args(&["--undo"]);
+        assert!(ParsedArgs::new(&args, 0).is_ok_and(|p| p.undo));
+        let args = to_args(&["--undo", "--help"]);
+        assert!(ParsedArgs::new(&args, 0).is_ok_and(|p| p.help && !p.undo));
+    }
+
+    #[test]
+    fn config_ends_with_a_list_option() {
+        let mut args = crate::arguments::config::parse_config("--smart-case\n-E\n");
+        let config_len = args.len();
+        args.push("./search_path".to_string());
+        args.push("search_query".to_string());
+        let parsed = match ParsedArgs::new(&args, config_len) {
+            Ok(p) => p,
+            Err(msg) => panic!("{}", msg)
+        };
+        assert_eq!(parsed.path, PathBuf::from("./search_path"));
+        assert_eq!(parsed.query, "search_query");
+        assert_eq!(parsed.exclude_file_types, Some(Vec::new()));
+        assert!(parsed.smart_case);
+    }
+}
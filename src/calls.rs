@@ -0,0 +1,195 @@
+// Structural search for --call NAME: finds calls to NAME with any
+// arguments by matching the parentheses after it. Parentheses in strings
+// and comments don't count, so a call is found whole even when it runs
+// over several lines.
+
+use std::collections::HashMap;
+
+use crate::lexer::{Lexer, Region};
+use crate::matcher::Span;
+use crate::replace;
+
+
+// The spans of every call to `name` in `text`, from the start of the name
+// to the closing parenthesis. Calls nested in the arguments of another are
+// part of its span.
+pub fn find_calls(text: &str, lexer: &mut Lexer, name: &str) -> Vec<Span> {
+
+    let mut spans: Vec<Span> = Vec::new();
+    let first_len = match name.chars().next() {
+        Some(c) => c.len_utf8(),
+        None => return spans
+    };
+    let code = code_mask(text, lexer);
+    let is_ident = |c: char| c.is_alphanumeric() || c == '_';
+
+    let mut i: usize = 0;
+    while let Some(found) = text[i..].find(name) {
+
+        let start = i + found;
+        let name_end = start + name.len();
+        i = start + first_len;
+
+        // A method of the same name (`x.print(`) is a different function
+        let before_ok = text[..start]
+            .chars()
+            .next_back()
+            .is_none_or(|c| !is_ident(c) && (c != '.' || name.starts_with('.')));
+        if !before_ok || !code[start..name_end].iter().all(|c| *c) {
+            continue
+        }
+
+        let open = name_end + text[name_end..].len() - text[name_end..].trim_start().len();
+        if !text[open..].starts_with('(') || !code[open] {
+            continue
+        }
+
+        let mut depth: usize = 0;
+        let close = text[open..].char_indices().find(|(offset, c)| {
+            if !code[open + offset] {
+                return false
+            }
+            match c {
+                '(' => depth += 1,
+                ')' => depth -= 1,
+                _ => {}
+            }
+            depth == 0
+        });
+        if let Some((offset, _)) = close {
+            let end = open + offset + 1;
+            spans.push(Span { start, end, pattern: 0, distance: 0 });
+            i = end;
+        }
+    }
+    spans
+}
+
+// For --replace: the new text of every line a call is on, with the spans
+// of the replacements in it. `$0` in the template is the whole call. A call
+// over several lines is replaced on its first line and taken off the rest.
+// Lines are trimmed as they are shown if `trim` is set. A line left blank is
+// taken out of the file when the replacements are written.
+pub fn call_replacements(
+    text: &str,
+    calls: &[Span],
+    template: &str,
+    trim: bool
+) -> HashMap<usize, (String, Vec<Span>)> {
+
+    let mut replacements: HashMap<usize, (String, Vec<Span>)> = HashMap::new();
+    let mut line_start: usize = 0;
+
+    for (idx, raw) in text.split_inclusive('\n').enumerate() {
+
+        let content = raw.strip_suffix('\n').unwrap_or(raw);
+        let content = content.strip_suffix('\r').unwrap_or(content);
+        let line_end = line_start + content.len();
+        let (line, offset) = match trim {
+            true => (content.trim(), content.len() - content.trim_start().len()),
+            false => (content, 0)
+        };
+
+        let mut new_line = String::new();
+        let mut new_spans: Vec<Span> = Vec::new();
+        let mut last_idx: usize = 0;
+        let mut changed = false;
+
+        let on_line = calls.iter().filter(|c| c.start <= line_end && c.end > line_start);
+        for call in on_line {
+            let local = |pos: usize| {
+                (pos.clamp(line_start, line_end) - line_start)
+                    .saturating_sub(offset)
+                    .min(line.len())
+            };
+            let (start, end) = (local(call.start), local(call.end));
+            new_line.push_str(&line[last_idx..start]);
+            if call.start >= line_start {
+                let whole = &text[call.start..call.end];
+                let new_text = replace::expand(template, |key| {
+                    (key == "0").then(|| whole.to_string())
+                });
+                let span_start = new_line.len();
+                new_line.push_str(&new_text);
+                new_spans.push(Span { start: span_start, end: new_line.len(), ..*call });
+            }
+            last_idx = end;
+            changed = true;
+        }
+
+        if changed {
+            new_line.push_str(&line[last_idx..]);
+            replacements.insert(idx + 1, (new_line, new_spans));
+        }
+        line_start += raw.len();
+    }
+    replacements
+}
+
+// Whether each byte of the text is code, rather than a comment or string
+fn code_mask(text: &str, lexer: &mut Lexer) -> Vec<bool> {
+    let mut code = vec![true; text.len()];
+    let mut line_start: usize = 0;
+    for raw in text.split_inclusive('\n') {
+        let content = raw.strip_suffix('\n').unwrap_or(raw);
+        let content = content.strip_suffix('\r').unwrap_or(content);
+        for (s, e, region) in lexer.regions(content) {
+            if region != Region::Code {
+                code[line_start + s..line_start + e].fill(false);
+            }
+        }
+        line_start += raw.len();
+    }
+    code
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn calls<'a>(ext: &str, name: &str, text: &'a str) -> Vec<&'a str> {
+        let mut lexer = match Lexer::for_extension(ext) {
+            Some(l) => l,
+            None => panic!("no lexer for .{}", ext)
+        };
+        find_calls(text, &mut lexer, name)
+            .into_iter()
+            .map(|s| &text[s.start..s.end])
+            .collect()
+    }
+
+    #[test]
+    fn balanced_calls() {
+        let source = "print(f(x), \")\")  # print(y)\nself.print(z)\nblueprint(a)\nprint (\n    'a(',\n    b)\n";
+        assert_eq!(
+            calls("py", "print", source),
+            vec!["print(f(x), \")\")", "print (\n    'a(',\n    b)"]
+        );
+        assert_eq!(calls("py", "self.print", source), vec!["self.print(z)"]);
+        assert_eq!(
+            calls("rs", "println!", "println!(\"{}\", (1 + 2)); println!"),
+            vec!["println!(\"{}\", (1 + 2))"]
+        );
+    }
+
+    #[test]
+    fn replacing_calls() {
+        let text = "a = 1\n  print(a,\n    b)  # done\nprint(c)";
+        let mut lexer = match Lexer::for_extension("py") {
+            Some(l) => l,
+            None => panic!("no lexer for .py")
+        };
+        let found = find_calls(text, &mut lexer, "print");
+        let new_lines = call_replacements(text, &found, "log($0)", false);
+        assert_eq!(new_lines.len(), 3);
+        assert_eq!(new_lines[&2].0, "  log(print(a,\n    b))");
+        assert_eq!(new_lines[&3].0, "  # done");
+        assert_eq!(new_lines[&4].0, "log(print(c))");
+
+        let removed = call_replacements(text, &found, "", true);
+        assert_eq!(removed[&2].0, "");
+        assert_eq!(removed[&3].0, "  # done");
+    }
+}
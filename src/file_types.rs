@@ -0,0 +1,260 @@
+// Named file types for -t/-T. A type is known by its extensions, by whole
+// file names like Makefile, and for scripts without an extension by the
+// interpreter on their #! line. Types are written as NAME:DEF,DEF,... where
+// each DEF is *.ext, a file name, or #!interpreter, both for the built-in
+// ones below and for --type-add.
+
+use std::{
+    fs,
+    io::{BufRead, BufReader, Read},
+    path::Path
+};
+
+
+const BUILT_IN: [&str; 26] = [
+    "c:*.c,*.h",
+    "cpp:*.cpp,*.cc,*.cxx,*.hpp,*.hh,*.hxx,*.h",
+    "csharp:*.cs",
+    "css:*.css,*.scss,*.sass,*.less",
+    "docker:Dockerfile,*.dockerfile",
+    "go:*.go",
+    "html:*.html,*.htm",
+    "java:*.java",
+    "js:*.js,*.jsx,*.mjs,*.cjs,#!node",
+    "json:*.json",
+    "kotlin:*.kt,*.kts",
+    "make:Makefile,makefile,GNUmakefile,*.mk",
+    "md:*.md,*.markdown",
+    "notebook:*.ipynb",
+    "perl:*.pl,*.pm,#!perl",
+    "php:*.php,#!php",
+    "py:*.py,*.pyi,*.pyx,#!python",
+    "ruby:*.rb,Gemfile,Rakefile,#!ruby",
+    "rust:*.rs",
+    "sh:*.sh,*.bash,*.zsh,.bashrc,.zshrc,.profile,#!sh,#!bash,#!zsh",
+    "sql:*.sql",
+    "swift:*.swift",
+    "toml:*.toml",
+    "ts:*.ts,*.tsx,*.mts,*.cts,#!deno",
+    "txt:*.txt",
+    "yaml:*.yaml,*.yml"
+];
+
+
+#[derive(Clone, Default)]
+pub struct FileType {
+    pub name: String,
+    extensions: Vec<String>,
+    names: Vec<String>,
+    interpreters: Vec<String>
+}
+
+impl FileType {
+
+    // An unnamed type for the extensions given to -I or -E
+    pub fn from_extensions(extensions: &[&str]) -> Self {
+        FileType {
+            extensions: extensions.iter().map(|e| e.to_string()).collect(),
+            ..FileType::default()
+        }
+    }
+
+    fn add(&mut self, defs: &str) -> Result<(), String> {
+        for def in defs.split(',').map(str::trim) {
+            if let Some(ext) = def.strip_prefix("*.") {
+                self.extensions.push(ext.to_string());
+            } else if let Some(interpreter) = def.strip_prefix("#!") {
+                self.interpreters.push(interpreter.to_string());
+            } else if def.is_empty() || def.contains(['*', '?', '/']) {
+                return Err(format!(
+                    "Invalid file type definition '{}' for {}: use *.ext, a file name or #!interpreter",
+                    def, self.name
+                ))
+            } else {
+                self.names.push(def.to_string());
+            }
+        }
+        Ok(())
+    }
+
+    fn matches(&self, file: &FileInfo) -> bool {
+        self.extensions.iter().any(|e| Some(e.as_str()) == file.extension)
+            || self.names.iter().any(|n| Some(n.as_str()) == file.name)
+            || file.interpreter.as_deref().is_some_and(|found| {
+                // python matches python3 and python3.12 too
+                self.interpreters.iter().any(|i| {
+                    found.strip_prefix(i.as_str())
+                        .is_some_and(|v| v.chars().all(|c| c.is_ascii_digit() || c == '.'))
+                })
+            })
+    }
+
+    fn describe(&self) -> String {
+        let mut defs: Vec<String> = self.extensions.iter().map(|e| format!("*.{}", e)).collect();
+        defs.extend(self.names.iter().cloned());
+        defs.extend(self.interpreters.iter().map(|i| format!("#!{}", i)));
+        defs.join(", ")
+    }
+}
+
+
+pub struct Types {
+    types: Vec<FileType>
+}
+
+impl Types {
+
+    // The built-in types plus the --type-add definitions. Adding to a type
+    // that already exists extends it.
+    pub fn new(additions: &[&str]) -> Result<Self, String> {
+        let mut types = Types { types: Vec::new() };
+        for def in BUILT_IN.iter().chain(additions) {
+            let (name, defs) = match def.split_once(':') {
+                Some((n, d)) if !n.trim().is_empty() => (n.trim(), d),
+                _ => return Err(format!(
+                    "Invalid file type '{}': expected NAME:DEF,DEF,...", def
+                ))
+            };
+            let position = types.types.iter().position(|t| t.name == name);
+            let file_type = match position {
+                Some(idx) => &mut types.types[idx],
+                None => {
+                    types.types.push(FileType { name: name.to_string(), ..FileType::default() });
+                    let last = types.types.len() - 1;
+                    &mut types.types[last]
+                }
+            };
+            file_type.add(defs)?;
+        }
+        types.types.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(types)
+    }
+
+    pub fn get(&self, name: &str) -> Result<&FileType, String> {
+        self.types
+            .iter()
+            .find(|t| t.name == name)
+            .ok_or(format!("Unknown file type '{}', see --type-list", name))
+    }
+
+    // For --type-list
+    pub fn list(&self) -> String {
+        let width = self.types.iter().map(|t| t.name.len()).max().unwrap_or(0);
+        self.types
+            .iter()
+            .map(|t| format!("{:width$}  {}", t.name, t.describe()))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+
+// What a file is searched for: -t and -I types it has to be one of, if any
+// are given, and -T and -E types it can't be
+#[derive(Default)]
+pub struct TypeFilter {
+    include: Vec<FileType>,
+    exclude: Vec<FileType>
+}
+
+impl TypeFilter {
+
+    pub fn new(include: Vec<FileType>, exclude: Vec<FileType>) -> Self {
+        TypeFilter { include, exclude }
+    }
+
+    pub fn allows_file(&self, path: &Path) -> bool {
+        if self.include.is_empty() && self.exclude.is_empty() {
+            return true
+        }
+        let file = FileInfo::of(path, self.include.iter().chain(&self.exclude));
+        !self.exclude.iter().any(|t| t.matches(&file))
+            && (self.include.is_empty() || self.include.iter().any(|t| t.matches(&file)))
+    }
+}
+
+
+struct FileInfo<'a> {
+    name: Option<&'a str>,
+    extension: Option<&'a str>,
+    interpreter: Option<String>
+}
+
+impl<'a> FileInfo<'a> {
+
+    // The #! line is only read for a file without an extension, and only
+    // when one of the types would look at it
+    fn of<'t>(path: &'a Path, mut types: impl Iterator<Item = &'t FileType>) -> Self {
+        let extension = path.extension().and_then(|e| e.to_str());
+        let interpreter = match extension.is_none() && types.any(|t| !t.interpreters.is_empty()) {
+            true => interpreter(path),
+            false => None
+        };
+        FileInfo {
+            name: path.file_name().and_then(|n| n.to_str()),
+            extension,
+            interpreter
+        }
+    }
+}
+
+// The program a script's #! line runs, e.g. python3 for both
+// #!/usr/bin/python3 and #!/usr/bin/env python3
+fn interpreter(path: &Path) -> Option<String> {
+    let file = fs::File::open(path).ok()?;
+    let mut first_line: Vec<u8> = Vec::new();
+    BufReader::new(file).take(256).read_until(b'\n', &mut first_line).ok()?;
+    let line = String::from_utf8_lossy(&first_line);
+    let mut words = line.strip_prefix("#!")?.split_whitespace();
+    let program = words.next()?.rsplit('/').next()?;
+    match program {
+        "env" => words.find(|w| !w.starts_with('-')).map(str::to_string),
+        _ => Some(program.to_string())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn match_file_types() {
+        let types = match Types::new(&["web:*.html,*.css", "py:SConstruct"]) {
+            Ok(t) => t,
+            Err(msg) => panic!("{}", msg)
+        };
+        let py = types.get("py").cloned().unwrap_or_default();
+        let make = types.get("make").cloned().unwrap_or_default();
+        let web = types.get("web").cloned().unwrap_or_default();
+        assert!(types.get("cobol").is_err());
+        assert!(Types::new(&["bad"]).is_err());
+        assert!(Types::new(&["bad:src/*.c"]).is_err());
+
+        let dir = std::env::temp_dir().join(format!("pscan_types_{}", std::process::id()));
+        let _ = fs::create_dir_all(&dir);
+        let script = dir.join("deploy");
+        let _ = fs::write(&script, "#!/usr/bin/env python3.12\nprint('hi')\n");
+        let shell = dir.join("build");
+        let _ = fs::write(&shell, "#!/bin/bash\necho hi\n");
+
+        let only_py = TypeFilter::new(vec![py.clone()], Vec::new());
+        assert!(only_py.allows_file(Path::new("a/models.pyi")));
+        assert!(only_py.allows_file(Path::new("SConstruct")));
+        assert!(only_py.allows_file(&script));
+        assert!(!only_py.allows_file(&shell));
+        assert!(!only_py.allows_file(Path::new("a/models.rs")));
+
+        let not_make = TypeFilter::new(Vec::new(), vec![make, web]);
+        assert!(!not_make.allows_file(Path::new("src/Makefile")));
+        assert!(!not_make.allows_file(Path::new("index.html")));
+        assert!(not_make.allows_file(Path::new("main.c")));
+
+        let extensions = TypeFilter::new(vec![FileType::from_extensions(&["txt"])], vec![py]);
+        assert!(extensions.allows_file(Path::new("notes.txt")));
+        assert!(!extensions.allows_file(&script));
+        let _ = fs::remove_dir_all(&dir);
+        assert!(types.list().contains("web       *.html, *.css"));
+    }
+}
@@ -0,0 +1,269 @@
+// Ignore files for recursive searches, with the semantics of .gitignore:
+//
+//     build/       a directory named build, anywhere below the file
+//     *.log        any file or directory matching, anywhere below the file
+//     /out         out, only in the directory the file is in
+//     docs/*.md    a pattern with a '/' in it is relative to that directory
+//     !keep.log    a match of this undoes the ignores before it
+//
+// .gitignore, .ignore and .pscanignore are read in every directory searched
+// (in that order, so a later file can override an earlier one), and in the
+// directories above the search root up to the top of its git repository,
+// along with the repository's .git/info/exclude. Deeper files take
+// precedence, and within them the last matching rule decides.
+
+use std::{
+    fs,
+    path::{Path, PathBuf}
+};
+
+use crate::matcher::{Regex, glob};
+
+
+const IGNORE_FILES: [&str; 3] = [".gitignore", ".ignore", ".pscanignore"];
+
+
+struct Rule {
+    regex: Regex,
+    negated: bool,
+    dir_only: bool
+}
+
+struct IgnoreFile {
+    // Paths below `base` are matched as `prefix` followed by their path
+    // from `base`. The prefix is only needed for the files above the
+    // search root.
+    base: PathBuf,
+    prefix: String,
+    rules: Vec<Rule>
+}
+
+pub struct Ignores {
+    files: Vec<IgnoreFile>,
+    enabled: bool
+}
+
+impl Ignores {
+
+    // For --no-ignore: nothing is read and nothing is ignored
+    pub fn none() -> Self {
+        Ignores { files: Vec::new(), enabled: false }
+    }
+
+    // The ignore files that apply to a search of `root`: those in the
+    // directories above it in its git repository, and its own
+    pub fn for_root(root: &Path) -> Self {
+
+        let mut ignores = Ignores { files: Vec::new(), enabled: true };
+        let abs_root = match fs::canonicalize(root) {
+            Ok(r) => r,
+            Err(_) => return ignores
+        };
+
+        let repo = abs_root.ancestors().find(|dir| dir.join(".git").exists());
+        if let Some(repo) = repo {
+            let above: Vec<&Path> = abs_root
+                .ancestors()
+                .skip(1)
+                .take_while(|dir| dir.starts_with(repo))
+                .collect();
+            let exclude = repo.join(".git").join("info").join("exclude");
+            ignores.load(&exclude, root, relative(&abs_root, repo));
+            for dir in above.into_iter().rev() {
+                for name in IGNORE_FILES {
+                    ignores.load(&dir.join(name), root, relative(&abs_root, dir));
+                }
+            }
+        }
+        ignores.enter(root);
+        ignores
+    }
+
+    // Adds the ignore files of a directory the search has gone into.
+    // Returns how many there were, to hand to `leave` when done with it.
+    pub fn enter(&mut self, dir: &Path) -> usize {
+        let before = self.files.len();
+        if !self.enabled {
+            return 0
+        }
+        for name in IGNORE_FILES {
+            self.load(&dir.join(name), dir, String::new());
+        }
+        self.files.len() - before
+    }
+
+    pub fn leave(&mut self, count: usize) {
+        self.files.truncate(self.files.len().saturating_sub(count));
+    }
+
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        for file in self.files.iter().rev() {
+            let rel = match path.strip_prefix(&file.base) {
+                Ok(r) => r,
+                Err(_) => continue
+            };
+            let rel = rel.to_string_lossy().replace('\\', "/");
+            let rel = match file.prefix.is_empty() {
+                true => rel,
+                false => format!("{}/{}", file.prefix, rel)
+            };
+            let rule = file.rules
+                .iter()
+                .rev()
+                .find(|r| (is_dir || !r.dir_only) && r.regex.is_match(&rel));
+            if let Some(r) = rule {
+                return !r.negated
+            }
+        }
+        false
+    }
+
+    fn load(&mut self, path: &Path, base: &Path, prefix: String) {
+        if let Ok(contents) = fs::read_to_string(path) {
+            let rules: Vec<Rule> = contents.lines().filter_map(parse_rule).collect();
+            if !rules.is_empty() {
+                self.files.push(IgnoreFile { base: base.to_path_buf(), prefix, rules });
+            }
+        }
+    }
+}
+
+// The -g/--glob filters, matched against paths from the search root. With
+// any plain globs given a file has to match one of them, and anything that
+// matches a glob starting with '!' is skipped, directories included. A glob
+// without a '/' matches the file name at any depth.
+pub struct GlobFilter {
+    include: Vec<Regex>,
+    exclude: Vec<Regex>
+}
+
+impl GlobFilter {
+
+    pub fn new(globs: &[&str]) -> Result<Self, String> {
+        let mut filter = GlobFilter { include: Vec::new(), exclude: Vec::new() };
+        for g in globs {
+            let (list, pattern) = match g.strip_prefix('!') {
+                Some(p) => (&mut filter.exclude, p),
+                None => (&mut filter.include, *g)
+            };
+            let pattern = match pattern.contains('/') {
+                true => pattern.trim_start_matches("./").to_string(),
+                false => format!("**/{}", pattern)
+            };
+            let regex = Regex::new(&glob::to_path_regex(&pattern), false)
+                .map_err(|msg| format!("Invalid glob '{}': {}", g, msg))?;
+            list.push(regex);
+        }
+        Ok(filter)
+    }
+
+    pub fn allows_file(&self, rel: &Path) -> bool {
+        let rel = slashed(rel);
+        !self.exclude.iter().any(|re| re.is_match(&rel))
+            && (self.include.is_empty() || self.include.iter().any(|re| re.is_match(&rel)))
+    }
+
+    // Whether to go into a directory. `!dir/**` skips it as well as `!dir`.
+    pub fn allows_dir(&self, rel: &Path) -> bool {
+        let rel = slashed(rel);
+        let inside = format!("{}/", rel);
+        !self.exclude.iter().any(|re| re.is_match(&rel) || re.is_match(&inside))
+    }
+}
+
+fn slashed(path: &Path) -> String {
+    path.to_string_lossy().replace('\\', "/")
+}
+
+fn parse_rule(line: &str) -> Option<Rule> {
+
+    let line = line.trim_end();
+    if line.is_empty() || line.starts_with('#') {
+        return None
+    }
+    let (negated, pattern) = match line.strip_prefix('!') {
+        Some(p) => (true, p),
+        None => (false, line.strip_prefix('\\').unwrap_or(line))
+    };
+    let (dir_only, pattern) = match pattern.strip_suffix('/') {
+        Some(p) => (true, p),
+        None => (false, pattern)
+    };
+    // A '/' anywhere but the end ties the pattern to the file's directory
+    let glob = match pattern.strip_prefix('/') {
+        Some(p) => p.to_string(),
+        None if pattern.contains('/') => pattern.to_string(),
+        None => format!("**/{}", pattern)
+    };
+    if glob.is_empty() {
+        return None
+    }
+    Regex::new(&glob::to_path_regex(&glob), false)
+        .ok()
+        .map(|regex| Rule { regex, negated, dir_only })
+}
+
+fn relative(path: &Path, base: &Path) -> String {
+    path.strip_prefix(base)
+        .map(|p| p.to_string_lossy().replace('\\', "/"))
+        .unwrap_or_default()
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn glob_filters() {
+        let filter = match GlobFilter::new(&["src/**/*.py", "*.toml", "!**/migrations/**"]) {
+            Ok(f) => f,
+            Err(msg) => panic!("{}", msg)
+        };
+        assert!(filter.allows_file(Path::new("src/app/models.py")));
+        assert!(filter.allows_file(Path::new("config/pscan.toml")));
+        assert!(!filter.allows_file(Path::new("tests/test_models.py")));
+        assert!(!filter.allows_file(Path::new("src/app/migrations/0001.py")));
+        assert!(!filter.allows_dir(Path::new("src/app/migrations")));
+        assert!(filter.allows_dir(Path::new("tests")));
+    }
+
+    #[test]
+    fn gitignore_rules() {
+        let root = std::env::temp_dir().join(format!("pscan_ignore_{}", std::process::id()));
+        let _ = fs::create_dir_all(root.join("app/logs"));
+        let _ = fs::write(
+            root.join(".gitignore"),
+            "# build output\ntarget/\n*.log\n!keep.log\n/top.txt\ndocs/*.md\n"
+        );
+        let _ = fs::write(root.join("app/.pscanignore"), "!debug.log\nvendor\n");
+
+        let mut ignores = Ignores::for_root(&root);
+        assert!(ignores.is_ignored(&root.join("target"), true));
+        assert!(!ignores.is_ignored(&root.join("target"), false));
+        assert!(ignores.is_ignored(&root.join("a.log"), false));
+        assert!(!ignores.is_ignored(&root.join("keep.log"), false));
+        assert!(ignores.is_ignored(&root.join("top.txt"), false));
+        assert!(ignores.is_ignored(&root.join("docs/a.md"), false));
+        assert!(!ignores.is_ignored(&root.join("docs/x/a.md"), false));
+
+        let app = root.join("app");
+        let added = ignores.enter(&app);
+        assert!(!ignores.is_ignored(&app.join("top.txt"), false));
+        assert!(ignores.is_ignored(&app.join("logs/a.log"), false));
+        assert!(!ignores.is_ignored(&app.join("debug.log"), false));
+        assert!(ignores.is_ignored(&app.join("vendor"), true));
+        ignores.leave(added);
+        assert!(!ignores.is_ignored(&root.join("vendor"), true));
+
+        // Files above the root still apply, relative to where they are
+        let nested = Ignores::for_root(&app);
+        let _ = fs::create_dir_all(root.join(".git"));
+        let in_repo = Ignores::for_root(&app);
+        let _ = fs::remove_dir_all(&root);
+        assert!(!nested.is_ignored(&app.join("x.log"), false));
+        assert!(in_repo.is_ignored(&app.join("x.log"), false));
+        assert!(!in_repo.is_ignored(&app.join("debug.log"), false));
+    }
+}
@@ -0,0 +1,363 @@
+// The undo journal for the modes that rewrite files. Before a file is
+// changed, the change is recorded in a journal under .pscan/journal/, one
+// journal per run: the file's path, hashes of its contents before and after
+// and every line that was replaced. `pscan --undo` puts the old lines back
+// in the files of the last run. If any of them has changed since, nothing
+// is restored.
+
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH}
+};
+
+use crate::replace;
+
+
+pub const JOURNAL_DIR: &str = ".pscan/journal";
+
+
+pub struct Entry {
+    pub path: PathBuf,
+    pub before_hash: u64,
+    pub after_hash: u64,
+    // number, old text, new text. A line that was taken out has no new
+    // text, and its old text keeps its line ending.
+    pub lines: Vec<(usize, String, Option<String>)>
+}
+
+impl Entry {
+
+    // The change to the file at `path` from `before` to `after`, which
+    // differ in the lines given by `new_lines`. `path` is recorded as it
+    // is, so it should be the one that is written to.
+    pub fn new(
+        path: &Path,
+        before: &str,
+        after: &str,
+        new_lines: &HashMap<usize, Option<String>>
+    ) -> Self {
+        let mut lines: Vec<(usize, String, Option<String>)> = before
+            .split_inclusive('\n')
+            .enumerate()
+            .filter_map(|(idx, line)| {
+                let new_line = new_lines.get(&(idx + 1))?;
+                let old_line = match line.strip_suffix('\n') {
+                    Some(l) if new_line.is_some() => l.strip_suffix('\r').unwrap_or(l),
+                    _ => line
+                };
+                Some((idx + 1, old_line.to_string(), new_line.clone()))
+            })
+            .collect();
+        lines.sort();
+
+        Entry {
+            path: path.to_path_buf(),
+            before_hash: hash(before),
+            after_hash: hash(after),
+            lines
+        }
+    }
+
+    fn to_text(&self) -> String {
+        let mut text = format!(
+            "file {}\nhash {:016x} {:016x}\n",
+            escape(&self.path.to_string_lossy()),
+            self.before_hash,
+            self.after_hash
+        );
+        for (num, old, new) in &self.lines {
+            text.push_str(&format!("line {}\n- {}\n", num, escape(old)));
+            match new {
+                Some(new) => text.push_str(&format!("+ {}\n", escape(new))),
+                None => text.push_str("removed\n")
+            }
+        }
+        text.push_str("end\n");
+        text
+    }
+}
+
+
+// 64 bit FNV-1a
+pub fn hash(contents: &str) -> u64 {
+    contents.bytes().fold(0xcbf2_9ce4_8422_2325, |h, b| {
+        (h ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+// A journal for a new run in `dir`. Names sort in the order the runs
+// happened.
+pub fn new_journal_path(dir: &Path) -> PathBuf {
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis());
+    dir.join(format!("{:016}-{}.journal", millis, std::process::id()))
+}
+
+// `dir` as an absolute path with symlinks resolved, whether it exists yet
+// or not, to compare the directories a search goes into with
+pub fn resolve_dir(dir: &Path) -> PathBuf {
+    if let Ok(resolved) = fs::canonicalize(dir) {
+        return resolved
+    }
+    match (dir.parent(), dir.file_name()) {
+        (Some(parent), Some(name)) if parent.as_os_str().is_empty() => {
+            resolve_dir(Path::new(".")).join(name)
+        },
+        (Some(parent), Some(name)) => resolve_dir(parent).join(name),
+        _ => dir.to_path_buf()
+    }
+}
+
+// Adds an entry to a journal, creating it if needed. Returns the length
+// the journal had before, for `truncate` if the file can't be written.
+pub fn record(journal: &Path, entry: &Entry) -> io::Result<u64> {
+    if let Some(dir) = journal.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut file = fs::OpenOptions::new().create(true).append(true).open(journal)?;
+    let start = file.metadata()?.len();
+    file.write_all(entry.to_text().as_bytes())?;
+    file.sync_all()?;
+    Ok(start)
+}
+
+// Cuts a journal back to `len` bytes, taking out the entries recorded
+// since. A journal left empty is deleted.
+pub fn truncate(journal: &Path, len: u64) -> io::Result<()> {
+    if len == 0 {
+        return fs::remove_file(journal)
+    }
+    let file = fs::OpenOptions::new().write(true).open(journal)?;
+    file.set_len(len)?;
+    file.sync_all()
+}
+
+pub fn read(journal: &Path) -> io::Result<Vec<Entry>> {
+
+    let contents = fs::read_to_string(journal)?;
+    let bad = || io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{} is not a valid journal", journal.display())
+    );
+
+    let mut entries: Vec<Entry> = Vec::new();
+    let mut lines = contents.lines();
+
+    while let Some(line) = lines.next() {
+        let path = line.strip_prefix("file ").ok_or_else(bad)?;
+        let hashes: Vec<u64> = lines
+            .next()
+            .and_then(|l| l.strip_prefix("hash "))
+            .ok_or_else(bad)?
+            .split(' ')
+            .map(|h| u64::from_str_radix(h, 16).map_err(|_| bad()))
+            .collect::<io::Result<_>>()?;
+        let [before_hash, after_hash] = hashes[..] else {
+            return Err(bad())
+        };
+
+        let mut entry = Entry {
+            path: PathBuf::from(unescape(path)),
+            before_hash,
+            after_hash,
+            lines: Vec::new()
+        };
+        loop {
+            match lines.next() {
+                Some("end") => break,
+                Some(l) if let Some(num) = l.strip_prefix("line ") => {
+                    let num = num.parse::<usize>().map_err(|_| bad())?;
+                    let old = lines.next().and_then(|l| l.strip_prefix("- "));
+                    let new = match lines.next() {
+                        Some("removed") => Some(None),
+                        l => l.and_then(|l| l.strip_prefix("+ ")).map(|n| Some(unescape(n)))
+                    };
+                    match (old, new) {
+                        (Some(o), Some(n)) => entry.lines.push((num, unescape(o), n)),
+                        _ => return Err(bad())
+                    }
+                },
+                _ => return Err(bad())
+            }
+        }
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+// The newest journal in `dir`, if there is one
+pub fn last_journal(dir: &Path) -> io::Result<Option<PathBuf>> {
+    if !dir.is_dir() {
+        return Ok(None)
+    }
+    let mut journals: Vec<PathBuf> = fs::read_dir(dir)?
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| p.extension().is_some_and(|e| e == "journal"))
+        .collect();
+    journals.sort();
+    Ok(journals.pop())
+}
+
+// Restores every file changed in a journal's run and then deletes the
+// journal, so the next undo goes back one run further. Returns the files
+// restored.
+pub fn undo(journal: &Path) -> Result<Vec<PathBuf>, String> {
+
+    let entries = read(journal).map_err(|e| e.to_string())?;
+
+    // Check every file before touching any of them
+    let mut restored: Vec<(&Path, String)> = Vec::new();
+    for entry in entries.iter().rev() {
+        let current = fs::read_to_string(&entry.path)
+            .map_err(|e| format!("Could not read {}: {}", entry.path.display(), e))?;
+        if hash(&current) != entry.after_hash {
+            return Err(format!(
+                "{} has changed since it was rewritten, nothing was restored",
+                entry.path.display()
+            ))
+        }
+        let original = restore_lines(&current, &entry.lines);
+        if hash(&original) != entry.before_hash {
+            return Err(format!(
+                "The journal doesn't match {}, nothing was restored",
+                entry.path.display()
+            ))
+        }
+        restored.push((&entry.path, original));
+    }
+
+    for (path, original) in &restored {
+        replace::write_atomic(path, original)
+            .map_err(|e| format!("Could not restore {}: {}", path.display(), e))?;
+    }
+    fs::remove_file(journal).map_err(|e| e.to_string())?;
+
+    Ok(restored.into_iter().map(|(p, _)| p.to_path_buf()).collect())
+}
+
+// The file's text with the old lines put back. A new line can have taken
+// up several lines of the file, when the replacement had line breaks in it,
+// and a line that was taken out is put back in between the others.
+fn restore_lines(contents: &str, changes: &[(usize, String, Option<String>)]) -> String {
+
+    let mut out = String::with_capacity(contents.len());
+    let mut lines = contents.split_inclusive('\n');
+    let mut changes = changes.iter().peekable();
+    let mut num: usize = 0;
+
+    loop {
+        num += 1;
+        if let Some((_, old, _)) = changes.next_if(|(n, _, new)| *n == num && new.is_none()) {
+            out.push_str(old);
+            continue
+        }
+        let Some(line) = lines.next() else { break };
+        match changes.next_if(|(n, _, _)| *n == num) {
+            Some((_, old, Some(new))) => {
+                let mut last = line;
+                for _ in 0..new.matches('\n').count() {
+                    last = lines.next().unwrap_or(last);
+                }
+                let ending = match last.strip_suffix('\n') {
+                    Some(l) if l.ends_with('\r') => "\r\n",
+                    Some(_) => "\n",
+                    None => ""
+                };
+                out.push_str(old);
+                out.push_str(ending);
+            },
+            _ => out.push_str(line)
+        }
+    }
+    out
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('\n', "\\n").replace('\r', "\\r")
+}
+
+fn unescape(text: &str) -> String {
+    let mut out = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match (c, c == '\\') {
+            (_, true) => match chars.next() {
+                Some('n') => out.push('\n'),
+                Some('r') => out.push('\r'),
+                Some(other) => out.push(other),
+                None => out.push('\\')
+            },
+            (c, false) => out.push(c)
+        }
+    }
+    out
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn escaping_round_trips() {
+        let text = "a\\b\r\nc\\n";
+        assert_eq!(unescape(&escape(text)), text);
+        assert!(!escape(text).contains('\n'));
+    }
+
+    #[test]
+    fn restore_multi_line_replacements() {
+        let changes = vec![(2, String::from("f(a,"), Some(String::from("g(f(a,\n b))"))), (3, String::from(" b)"), Some(String::new()))];
+        assert_eq!(restore_lines("x\ng(f(a,\n b))\n\ny\n", &changes), "x\nf(a,\n b)\ny\n");
+    }
+
+    #[test]
+    fn restore_removed_lines() {
+        let changes = vec![(1, String::from("f(a,\n"), None), (2, String::from(" b)\r\n"), None), (5, String::from("g()"), None)];
+        assert_eq!(restore_lines("x\ny\n", &changes), "f(a,\n b)\r\nx\ny\ng()");
+    }
+
+    #[test]
+    fn record_and_undo() {
+        let dir = std::env::temp_dir().join(format!("pscan_journal_{}", std::process::id()));
+        let _ = fs::create_dir_all(&dir);
+        let file = dir.join("notes.txt");
+        let before = "keep\r\nprint(x)\nkeep\n";
+        let _ = fs::write(&file, before);
+
+        let mut new_lines: HashMap<usize, Option<String>> = HashMap::new();
+        new_lines.insert(2, Some(String::from("pass")));
+        new_lines.insert(3, None);
+        let after = replace::rewrite_lines(before, &new_lines);
+        let journal = new_journal_path(&dir.join("journal"));
+        let entry = Entry::new(&file, before, &after, &new_lines);
+        assert!(record(&journal, &entry).is_ok());
+        let _ = fs::write(&file, &after);
+
+        assert_eq!(last_journal(&dir.join("journal")).ok().flatten(), Some(journal.clone()));
+        let entries = match read(&journal) {
+            Ok(e) => e,
+            Err(msg) => panic!("{}", msg)
+        };
+        assert_eq!(entries[0].lines, vec![
+            (2, String::from("print(x)"), Some(String::from("pass"))),
+            (3, String::from("keep\n"), None)
+        ]);
+
+        // A file edited after the run is left alone
+        let _ = fs::write(&file, "edited");
+        assert!(undo(&journal).is_err());
+
+        let _ = fs::write(&file, &after);
+        assert!(undo(&journal).is_ok());
+        assert_eq!(fs::read_to_string(&file).unwrap_or_default(), before);
+        assert!(!journal.exists());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
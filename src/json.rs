@@ -0,0 +1,268 @@
+// A small JSON parser, enough to read Jupyter notebooks. Objects keep their
+// keys in the order they were written.
+
+
+// Arrays and objects are parsed recursively, so how deep they can nest is
+// capped to keep a malformed file from overflowing the stack
+const MAX_DEPTH: usize = 256;
+
+#[derive(Debug, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>)
+}
+
+impl Value {
+
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(fields) => fields.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Value::Array(items) => Some(items),
+            _ => None
+        }
+    }
+}
+
+
+pub fn parse(text: &str) -> Result<Value, String> {
+    let mut parser = Parser { text, pos: 0, depth: 0 };
+    let value = parser.value()?;
+    parser.skip_whitespace();
+    match parser.pos == text.len() {
+        true => Ok(value),
+        false => Err(parser.error("end of input"))
+    }
+}
+
+
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+    depth: usize
+}
+
+impl<'a> Parser<'a> {
+
+    fn error(&self, expected: &str) -> String {
+        format!("invalid JSON at byte {}: expected {}", self.pos, expected)
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.text.as_bytes().get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, token: &str) -> Result<(), String> {
+        match self.text[self.pos..].starts_with(token) {
+            true => {
+                self.pos += token.len();
+                Ok(())
+            },
+            false => Err(self.error(&format!("'{}'", token)))
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(b'{') => self.nested(Self::object),
+            Some(b'[') => self.nested(Self::array),
+            Some(b'"') => self.string().map(Value::String),
+            Some(b't') => self.expect("true").map(|_| Value::Bool(true)),
+            Some(b'f') => self.expect("false").map(|_| Value::Bool(false)),
+            Some(b'n') => self.expect("null").map(|_| Value::Null),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            _ => Err(self.error("a value"))
+        }
+    }
+
+    fn nested(&mut self, parse: fn(&mut Self) -> Result<Value, String>) -> Result<Value, String> {
+        if self.depth == MAX_DEPTH {
+            return Err(format!("invalid JSON at byte {}: nested too deeply", self.pos))
+        }
+        self.depth += 1;
+        let value = parse(self);
+        self.depth -= 1;
+        value
+    }
+
+    fn object(&mut self) -> Result<Value, String> {
+        self.expect("{")?;
+        let mut fields: Vec<(String, Value)> = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            return Ok(Value::Object(fields))
+        }
+        loop {
+            self.skip_whitespace();
+            let key = self.string()?;
+            self.skip_whitespace();
+            self.expect(":")?;
+            fields.push((key, self.value()?));
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => {
+                    self.pos += 1;
+                    return Ok(Value::Object(fields))
+                },
+                _ => return Err(self.error("',' or '}'"))
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Value, String> {
+        self.expect("[")?;
+        let mut items: Vec<Value> = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+            return Ok(Value::Array(items))
+        }
+        loop {
+            items.push(self.value()?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {
+                    self.pos += 1;
+                    return Ok(Value::Array(items))
+                },
+                _ => return Err(self.error("',' or ']'"))
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<Value, String> {
+        let start = self.pos;
+        while matches!(self.peek(), Some(b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9')) {
+            self.pos += 1;
+        }
+        self.text[start..self.pos]
+            .parse::<f64>()
+            .map(Value::Number)
+            .map_err(|_| {
+                self.pos = start;
+                self.error("a number")
+            })
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect("\"")?;
+        let mut out = String::new();
+        loop {
+            let rest = &self.text[self.pos..];
+            let c = match rest.chars().next() {
+                Some(c) => c,
+                None => return Err(self.error("'\"'"))
+            };
+            self.pos += c.len_utf8();
+            match c {
+                '"' => return Ok(out),
+                '\\' => {
+                    let escaped = match self.peek() {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'/') => '/',
+                        Some(b'b') => '\u{8}',
+                        Some(b'f') => '\u{c}',
+                        Some(b'n') => '\n',
+                        Some(b'r') => '\r',
+                        Some(b't') => '\t',
+                        Some(b'u') => {
+                            self.pos += 1;
+                            let unit = self.hex4()?;
+                            out.push(self.code_point(unit)?);
+                            continue
+                        },
+                        _ => return Err(self.error("an escape"))
+                    };
+                    self.pos += 1;
+                    out.push(escaped);
+                },
+                c => out.push(c)
+            }
+        }
+    }
+
+    fn hex4(&mut self) -> Result<u32, String> {
+        let digits = self.text.get(self.pos..self.pos + 4).ok_or(self.error("4 hex digits"))?;
+        let unit = u32::from_str_radix(digits, 16).map_err(|_| self.error("4 hex digits"))?;
+        self.pos += 4;
+        Ok(unit)
+    }
+
+    // A \u escape, joining a surrogate pair into one character
+    fn code_point(&mut self, unit: u32) -> Result<char, String> {
+        if (0xd800..0xdc00).contains(&unit) && self.text[self.pos..].starts_with("\\u") {
+            self.pos += 2;
+            let low = self.hex4()?;
+            let c = 0x10000 + ((unit - 0xd800) << 10) + (low.wrapping_sub(0xdc00) & 0x3ff);
+            return Ok(char::from_u32(c).unwrap_or('\u{fffd}'))
+        }
+        Ok(char::from_u32(unit).unwrap_or('\u{fffd}'))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn parse_values() {
+        let value = match parse(r#" {"a": [1, -2.5e1, true, null], "b": {"c": "x\"\n\u00e9\ud83d\ude00"}} "#) {
+            Ok(v) => v,
+            Err(msg) => panic!("{}", msg)
+        };
+        assert_eq!(
+            value.get("a").and_then(Value::as_array),
+            Some(&[Value::Number(1.0), Value::Number(-25.0), Value::Bool(true), Value::Null][..])
+        );
+        assert_eq!(
+            value.get("b").and_then(|b| b.get("c")).and_then(Value::as_str),
+            Some("x\"\né😀")
+        );
+    }
+
+    #[test]
+    fn reject_invalid() {
+        assert!(parse("{\"a\": }").is_err());
+        assert!(parse("[1, 2").is_err());
+        assert!(parse("\"open").is_err());
+        assert!(parse("{} x").is_err());
+    }
+
+    #[test]
+    fn reject_deep_nesting() {
+        let deep = |n: usize| format!("{}{}", "[".repeat(n), "]".repeat(n));
+        assert!(parse(&deep(MAX_DEPTH)).is_ok());
+        assert!(parse(&deep(MAX_DEPTH + 1)).is_err());
+        assert!(parse(&"[".repeat(100_000)).is_err());
+        assert!(parse(&"{\"a\":".repeat(100_000)).is_err());
+    }
+}
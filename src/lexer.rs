@@ -0,0 +1,373 @@
+// Splits source lines into code, comments and string literals for
+// --code-only, --comments-only and --strings-only. Each language is only
+// described well enough to tell where its comments and strings start and
+// end. The lexer keeps its state from one line to the next, so block
+// comments and strings that span lines are followed.
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Region {
+    Code,
+    Comment,
+    String
+}
+
+struct Quote {
+    open: &'static str,
+    close: &'static str,
+    escapes: bool,
+    multiline: bool
+}
+
+struct Language {
+    line_comments: &'static [&'static str],
+    block_comment: Option<(&'static str, &'static str)>,
+    nested_comments: bool,
+    quotes: &'static [Quote],
+    // In shell scripts '#' only starts a comment at the start of a word,
+    // so $# and ${#var} are code
+    comment_at_word_start: bool,
+    // Rust's raw strings (r"...", r#"..."#) and char literals, which have
+    // to be told apart from lifetimes
+    rust_literals: bool
+}
+
+const fn quote(open: &'static str, escapes: bool, multiline: bool) -> Quote {
+    Quote { open, close: open, escapes, multiline }
+}
+
+const PYTHON: Language = Language {
+    line_comments: &["#"],
+    block_comment: None,
+    nested_comments: false,
+    quotes: &[
+        quote("\"\"\"", true, true),
+        quote("'''", true, true),
+        quote("\"", true, false),
+        quote("'", true, false)
+    ],
+    comment_at_word_start: false,
+    rust_literals: false
+};
+
+const RUST: Language = Language {
+    line_comments: &["//"],
+    block_comment: Some(("/*", "*/")),
+    nested_comments: true,
+    quotes: &[quote("\"", true, true)],
+    comment_at_word_start: false,
+    rust_literals: true
+};
+
+const JAVASCRIPT: Language = Language {
+    line_comments: &["//"],
+    block_comment: Some(("/*", "*/")),
+    nested_comments: false,
+    quotes: &[
+        quote("`", true, true),
+        quote("\"", true, false),
+        quote("'", true, false)
+    ],
+    comment_at_word_start: false,
+    rust_literals: false
+};
+
+const C_FAMILY: Language = Language {
+    line_comments: &["//"],
+    block_comment: Some(("/*", "*/")),
+    nested_comments: false,
+    quotes: &[quote("\"", true, false), quote("'", true, false)],
+    comment_at_word_start: false,
+    rust_literals: false
+};
+
+const SHELL: Language = Language {
+    line_comments: &["#"],
+    block_comment: None,
+    nested_comments: false,
+    quotes: &[quote("\"", true, true), quote("'", false, true)],
+    comment_at_word_start: true,
+    rust_literals: false
+};
+
+
+#[derive(Clone, Copy)]
+enum State {
+    Code,
+    Comment(usize),     // how deeply nested
+    Quote(usize),       // which of the language's quotes
+    RawString(usize)    // how many '#'s close it
+}
+
+pub struct Lexer {
+    language: &'static Language,
+    state: State
+}
+
+impl Lexer {
+
+    // A lexer for files with the given extension, if it is a language the
+    // lexer knows
+    pub fn for_extension(ext: &str) -> Option<Self> {
+        let language = match ext {
+            "py" | "pyw" | "pyi" => &PYTHON,
+            "rs" => &RUST,
+            "js" | "mjs" | "cjs" | "jsx" | "ts" | "mts" | "cts" | "tsx" => &JAVASCRIPT,
+            "c" | "h" | "cc" | "cpp" | "cxx" | "hpp" | "hh" | "hxx" |
+            "java" | "cs" | "go" | "kt" | "kts" | "scala" | "swift" => &C_FAMILY,
+            "sh" | "bash" | "zsh" | "ksh" => &SHELL,
+            _ => return None
+        };
+        Some(Lexer { language, state: State::Code })
+    }
+
+    // The regions of the next line of the file as (start, end, region),
+    // covering the whole line in order
+    pub fn regions(&mut self, line: &str) -> Vec<(usize, usize, Region)> {
+
+        let lang = self.language;
+        let mut regions: Vec<(usize, usize, Region)> = Vec::new();
+        let mut push = |start: usize, end: usize, region: Region| {
+            if start < end {
+                regions.push((start, end, region));
+            }
+        };
+        let mut start: usize = 0;
+        let mut i: usize = 0;
+
+        while i < line.len() {
+            let rest = &line[i..];
+            match self.state {
+                State::Code => {
+                    if let Some((state, len)) = self.opening(line, i) {
+                        push(start, i, Region::Code);
+                        start = i;
+                        i += len;
+                        match state {
+                            // A char literal, already over
+                            None => {
+                                push(start, i, Region::String);
+                                start = i;
+                            },
+                            Some(s) => self.state = s
+                        }
+                        continue
+                    }
+                    if lang.line_comments.iter().any(|c| rest.starts_with(c))
+                        && (!lang.comment_at_word_start || at_word_start(line, i)) {
+                        push(start, i, Region::Code);
+                        push(i, line.len(), Region::Comment);
+                        return regions
+                    }
+                },
+                State::Comment(depth) => {
+                    if let Some((open, close)) = lang.block_comment {
+                        if lang.nested_comments && rest.starts_with(open) {
+                            self.state = State::Comment(depth + 1);
+                            i += open.len();
+                            continue
+                        }
+                        if rest.starts_with(close) {
+                            i += close.len();
+                            self.state = match depth {
+                                1 => {
+                                    push(start, i, Region::Comment);
+                                    start = i;
+                                    State::Code
+                                },
+                                _ => State::Comment(depth - 1)
+                            };
+                            continue
+                        }
+                    }
+                },
+                State::Quote(idx) => {
+                    let quote = &lang.quotes[idx];
+                    if quote.escapes && rest.starts_with('\\') {
+                        i += 1 + char_len(&line[i + 1..]);
+                        continue
+                    }
+                    if rest.starts_with(quote.close) {
+                        i += quote.close.len();
+                        push(start, i, Region::String);
+                        start = i;
+                        self.state = State::Code;
+                        continue
+                    }
+                },
+                State::RawString(hashes) => {
+                    let closed = rest.starts_with('"')
+                        && rest[1..].bytes().take_while(|b| *b == b'#').count() >= hashes;
+                    if closed {
+                        i += 1 + hashes;
+                        push(start, i, Region::String);
+                        start = i;
+                        self.state = State::Code;
+                        continue
+                    }
+                }
+            }
+            i += char_len(rest);
+        }
+
+        let region = match self.state {
+            State::Code => Region::Code,
+            State::Comment(_) => Region::Comment,
+            State::Quote(_) | State::RawString(_) => Region::String
+        };
+        push(start, line.len(), region);
+
+        // Quotes that can't span lines end with the line anyway
+        if let State::Quote(idx) = self.state
+            && !lang.quotes[idx].multiline {
+            self.state = State::Code;
+        }
+        regions
+    }
+
+    // A block comment or string starting at `i`, as the state it starts
+    // and the length of its opening. A char literal is returned whole,
+    // with no state.
+    fn opening(&self, line: &str, i: usize) -> Option<(Option<State>, usize)> {
+
+        let lang = self.language;
+        let rest = &line[i..];
+
+        if let Some((open, _)) = lang.block_comment
+            && rest.starts_with(open) {
+            return Some((Some(State::Comment(1)), open.len()))
+        }
+        if lang.rust_literals {
+            let after_ident = line[..i]
+                .chars()
+                .next_back()
+                .is_some_and(|c| c.is_alphanumeric() || c == '_');
+            let raw = rest.strip_prefix("br").or_else(|| rest.strip_prefix('r'));
+            if let Some(r) = raw
+                && !after_ident {
+                let hashes = r.bytes().take_while(|b| *b == b'#').count();
+                if r[hashes..].starts_with('"') {
+                    let len = rest.len() - r.len() + hashes + 1;
+                    return Some((Some(State::RawString(hashes)), len))
+                }
+            }
+            if rest.starts_with('\'') {
+                return char_literal(rest).map(|len| (None, len))
+            }
+        }
+        lang.quotes
+            .iter()
+            .position(|q| rest.starts_with(q.open))
+            .map(|idx| (Some(State::Quote(idx)), lang.quotes[idx].open.len()))
+    }
+}
+
+// Whether every part of start..end is in `region`
+pub fn within(regions: &[(usize, usize, Region)], start: usize, end: usize, region: Region) -> bool {
+    regions
+        .iter()
+        .filter(|(s, e, _)| *s < end.max(start + 1) && start < *e)
+        .all(|(_, _, r)| *r == region)
+}
+
+fn char_len(text: &str) -> usize {
+    text.chars().next().map_or(1, char::len_utf8)
+}
+
+fn at_word_start(line: &str, i: usize) -> bool {
+    line[..i]
+        .chars()
+        .next_back()
+        .is_none_or(|c| c.is_whitespace() || matches!(c, ';' | '|' | '&' | '(' | ')'))
+}
+
+// Length of the Rust char literal at the start of `text`, or None if the
+// quote starts a lifetime or label
+fn char_literal(text: &str) -> Option<usize> {
+    let body = &text[1..];
+    let len = match body.strip_prefix('\\') {
+        Some(escaped) => {
+            let after = 1 + char_len(escaped);
+            1 + after + body.get(after..)?.find('\'')?
+        },
+        None => {
+            let c = char_len(body);
+            match body[c.min(body.len())..].starts_with('\'') {
+                true => 1 + c,
+                false => return None
+            }
+        }
+    };
+    Some(len + 1)
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    // The text of each region of each line, in the lexer's order
+    fn lex(ext: &str, source: &str) -> Vec<(String, Region)> {
+        let mut lexer = match Lexer::for_extension(ext) {
+            Some(l) => l,
+            None => panic!("no lexer for .{}", ext)
+        };
+        source
+            .lines()
+            .flat_map(|line| {
+                lexer.regions(line)
+                    .into_iter()
+                    .map(|(s, e, r)| (line[s..e].to_string(), r))
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    fn of(regions: &[(String, Region)], region: Region) -> Vec<&str> {
+        regions.iter().filter(|(_, r)| *r == region).map(|(t, _)| t.as_str()).collect()
+    }
+
+    #[test]
+    fn python_strings_and_comments() {
+        let found = lex("py", "print('#', x)  # print(x)\ns = \"\"\"a\nprint\"\"\" + 'it\\'s'");
+        assert_eq!(of(&found, Region::Comment), vec!["# print(x)"]);
+        assert_eq!(of(&found, Region::String), vec!["'#'", "\"\"\"a", "print\"\"\"", "'it\\'s'"]);
+        assert_eq!(of(&found, Region::Code)[0], "print(");
+    }
+
+    #[test]
+    fn rust_literals_and_nested_comments() {
+        let found = lex("rs", "/* a /* b */ c */ f::<'a>('x', r#\"\"q\"#, '\\'');");
+        assert_eq!(of(&found, Region::Comment), vec!["/* a /* b */ c */"]);
+        assert_eq!(of(&found, Region::String), vec!["'x'", "r#\"\"q\"#", "'\\''"]);
+        assert!(of(&found, Region::Code).concat().contains("f::<'a>("));
+    }
+
+    #[test]
+    fn unterminated_char_literals() {
+        for source in ["let c = '\\", "let c = '\\n", "let c = 'x", "foo('"] {
+            let found = lex("rs", source);
+            assert_eq!(found.iter().map(|(t, _)| t.as_str()).collect::<String>(), source);
+        }
+    }
+
+    #[test]
+    fn state_carries_across_lines() {
+        let found = lex("js", "let s = `one\ntwo`; /* x\ny */ z; 'unclosed\nw");
+        assert_eq!(of(&found, Region::String), vec!["`one", "two`", "'unclosed"]);
+        assert_eq!(of(&found, Region::Comment), vec!["/* x", "y */"]);
+        assert_eq!(of(&found, Region::Code).last(), Some(&"w"));
+
+        let found = lex("sh", "echo $# ${#a} # count");
+        assert_eq!(of(&found, Region::Comment), vec!["# count"]);
+    }
+
+    #[test]
+    fn spans_within_a_region() {
+        let regions = vec![(0, 5, Region::Code), (5, 9, Region::String)];
+        assert!(within(&regions, 5, 9, Region::String));
+        assert!(!within(&regions, 3, 7, Region::String));
+        assert!(within(&regions, 0, 2, Region::Code));
+    }
+}
@@ -1,22 +1,39 @@
 use std::{
-    collections::VecDeque, 
+    collections::{HashMap, VecDeque}, 
     env, 
     fmt, 
     fs,
     ffi::OsStr,
     io::{BufReader, BufRead}, 
-    path::Path,
-    cmp::min
+    path::{Path, PathBuf}
 };
 
 use crate::arguments::parser::{ParsedArgs, HELP_TEXT};
+use crate::file_types::{FileType, TypeFilter, Types};
+use crate::ignore::{GlobFilter, Ignores};
+use crate::lexer::Lexer;
+use crate::matcher::{Matcher, Span};
+use crate::notebook::Notebook;
+use crate::replace::Confirmation;
+use crate::scope::{Header, ScopeTracker};
 pub mod arguments;
+pub mod calls;
+pub mod file_types;
+pub mod ignore;
+pub mod journal;
+pub mod json;
+pub mod lexer;
+pub mod matcher;
+pub mod notebook;
+pub mod replace;
+pub mod scope;
 
 
 pub enum PscanError {
     FileRead,
     Argument,
-    InputError
+    InputError,
+    Pattern
 }
 
 impl fmt::Display for PscanError {
@@ -24,7 +41,8 @@ impl fmt::Display for PscanError {
         let msg: &str = match &self {
             Self::FileRead => "FileRead", 
             Self::Argument => "Argument",
-            Self::InputError => "InputError"
+            Self::InputError => "InputError",
+            Self::Pattern => "Pattern"
         }; 
         write!(f, "{}", msg)
     }
@@ -63,63 +81,253 @@ pub fn error_handler(error_response: ErrorResponse) -> i32 {
         PscanError::InputError => {
             err_msg.push_str("InputError: ");
             exit_code = 2;
+        },
+        PscanError::Pattern => {
+            err_msg.push_str("PatternError: ");
+            exit_code = 2;
         }
     }
     
-    err_msg.push_str(&format!("{}", error_response.error_msg));
+    err_msg.push_str(&error_response.error_msg);
     eprintln!("{}\x1b[0m", err_msg);
     exit_code
 
 }
 
 
+// A line to be printed: either a match, holding the byte span of every 
+// non-overlapping hit on it, or a line of context with no spans. With -v
+// the non-matching lines are the matches, and they have no spans either.
+// With --replace a match also has the line as it is after replacing, and 
+// the spans of the new text in it. With --scope every line has the header
+// of the definition it is in, if any. With -U the first span can be the
+// rest of a match that started on an earlier line, which isn't counted
+// again.
+pub struct MatchedLine {
+    pub line_num: usize,
+    pub line: String,
+    pub spans: Vec<Span>,
+    pub continues: bool,
+    pub is_match: bool,
+    pub replacement: Option<(String, Vec<Span>)>,
+    pub scope: Option<Header>
+}
+
+// What a run keeps track of as it goes, passed to `run`
+pub struct RunContext {
+    // Where undo journals are kept, .pscan/journal by default
+    pub journal_dir: PathBuf,
+    // The journal of the current run, started by the first file it writes
+    journal: Option<PathBuf>,
+    // Answers to --interactive prompts, kept for the whole run so that [a]ll
+    // and [q]uit carry on into the files after the one they were given in
+    confirmation: Confirmation
+}
+
+impl RunContext {
+
+    pub fn new() -> Self {
+        RunContext::with_journal_dir(Path::new(journal::JOURNAL_DIR))
+    }
+
+    pub fn with_journal_dir(dir: &Path) -> Self {
+        RunContext {
+            journal_dir: dir.to_path_buf(),
+            journal: None,
+            confirmation: Confirmation::new()
+        }
+    }
+}
+
+impl Default for RunContext {
+    fn default() -> Self {
+        RunContext::new()
+    }
+}
+
+// What decides which files and directories a search goes into
+struct Filters {
+    ignores: Ignores,
+    globs: GlobFilter,
+    types: TypeFilter,
+    // Never searched, so a run can't rewrite its own journals
+    journal_dir: PathBuf
+}
+
+// Colours of the text taken out and put in by --replace
+const REMOVED: &str = "\x1b[1;9;31m";
+const ADDED: &str = "\x1b[1;32m";
+
+// Highlight colours for --distinct-colors, one per pattern in turn
+const PATTERN_COLORS: [&str; 6] = [
+    "\x1b[1;33m",  // yellow
+    "\x1b[1;31m",  // red
+    "\x1b[1;32m",  // green
+    "\x1b[1;34m",  // blue
+    "\x1b[1;35m",  // magenta
+    "\x1b[1;36m"   // cyan
+];
+
+
 fn process_paths_from_args(
-    parsed_args: &ParsedArgs
+    parsed_args: &ParsedArgs,
+    context: &mut RunContext
 ) -> Result<usize, ErrorResponse> {
 
-    fn file_is_ignored<'a>(
+    fn file_is_ignored(
         path: &Path, 
-        parsed_args: &ParsedArgs 
+        parsed_args: &ParsedArgs,
+        filters: &Filters
     ) -> bool {
-        
-        let ext = get_extension(&path);
-        let ext_str: &str = &ext;
-        let mut should_ignore: bool = match &parsed_args.exclude_file_types {
-            Some(e) => {
-                if ext.len() > 0 && e.contains(&ext_str) {
-                    true     
-                } else {
-                    false
-                }
-            },
-            None => false
+        !filters.globs.allows_file(path_from_root(path, parsed_args)) 
+            || !filters.types.allows_file(path)
+    }
+
+    // Whether to search a file, or go into a directory, found `depth`
+    // levels below PATH
+    fn should_visit(
+        path: &Path,
+        depth: usize,
+        is_dir: bool,
+        parsed_args: &ParsedArgs,
+        filters: &Filters
+    ) -> bool {
+        !is_journal_dir(path, is_dir, filters)
+            && !filters.ignores.is_ignored(path, is_dir)
+            && (parsed_args.hidden || !is_hidden(path))
+            && in_depth_range(depth, is_dir, parsed_args)
+            && (!is_dir || filters.globs.allows_dir(path_from_root(path, parsed_args)))
+    }
+
+    // The -t/-I types a file has to be and the -T/-E types it can't be
+    fn type_filter(parsed_args: &ParsedArgs) -> Result<TypeFilter, ErrorResponse> {
+        let types = Types::new(&parsed_args.type_adds)
+            .map_err(|msg| ErrorResponse::new(msg, PscanError::Argument))?;
+        let named = |names: &[&str]| {
+            names
+                .iter()
+                .map(|name| types.get(name).cloned())
+                .collect::<Result<Vec<FileType>, String>>()
+                .map_err(|msg| ErrorResponse::new(msg, PscanError::Argument))
         };
-        if !should_ignore {  // Because it's not been excluded
-            if let Some(i) = &parsed_args.include_file_types {
-                if ext.len() > 0 && i.contains(&ext_str) {
-                    should_ignore = false 
-                } else {
-                    should_ignore = true
-                }
+        let mut include = named(&parsed_args.types)?;
+        let mut exclude = named(&parsed_args.types_not)?;
+        if let Some(extensions) = &parsed_args.include_file_types {
+            include.push(FileType::from_extensions(extensions));
+        }
+        if let Some(extensions) = &parsed_args.exclude_file_types {
+            exclude.push(FileType::from_extensions(extensions));
+        }
+        Ok(TypeFilter::new(include, exclude))
+    }
+
+    // The path -g globs are matched against. A file given as PATH is
+    // matched by its name.
+    fn path_from_root<'a>(path: &'a Path, parsed_args: &ParsedArgs) -> &'a Path {
+        match path.strip_prefix(&parsed_args.path) {
+            Ok(rel) if !rel.as_os_str().is_empty() => rel,
+            _ => path.file_name().map_or(path, Path::new)
+        }
+    }
+
+    // Whether a file `depth` levels below PATH is searched, or for a
+    // directory, whether to go into it. The entries of PATH are at depth 1.
+    fn in_depth_range(depth: usize, is_dir: bool, parsed_args: &ParsedArgs) -> bool {
+        match is_dir {
+            true => parsed_args.max_depth.is_none_or(|max| depth < max),
+            false => {
+                depth >= parsed_args.min_depth 
+                    && parsed_args.max_depth.is_none_or(|max| depth <= max)
             }
-        };
-        should_ignore
+        }
+    }
+
+    fn is_journal_dir(path: &Path, is_dir: bool, filters: &Filters) -> bool {
+        is_dir 
+            && path.file_name() == filters.journal_dir.file_name()
+            && fs::canonicalize(path).is_ok_and(|p| p == filters.journal_dir)
+    }
+
+    // Dotfiles and dot-directories, skipped unless --hidden is given
+    fn is_hidden(path: &Path) -> bool {
+        path.file_name()
+            .and_then(OsStr::to_str)
+            .is_some_and(|name| name.starts_with('.'))
     }
 
     fn get_extension(path: &Path) -> String {
         let ext = path.extension();
-        if let Some(p) = ext {
-            if let Some(d) = p.to_str() {
-                return d.to_string() 
-            }
+        if let Some(p) = ext
+            && let Some(d) = p.to_str() {
+            return d.to_string() 
         }
         "".to_string()
     }
 
+    // The positional QUERY plus every -e pattern and every line of the -f
+    // pattern files
+    fn collect_patterns(
+        parsed_args: &ParsedArgs
+    ) -> Result<Vec<String>, ErrorResponse> {
+
+        let mut patterns: Vec<String> = Vec::new();
+        if !parsed_args.query.is_empty() {
+            patterns.push(parsed_args.query.to_string());
+        }
+        patterns.extend(parsed_args.patterns.iter().map(|p| p.to_string()));
+        if let Some(name) = parsed_args.call {
+            patterns.push(name.to_string());
+        }
+
+        for file_name in &parsed_args.pattern_files {
+            let contents = match fs::read_to_string(file_name) {
+                Ok(c) => c,
+                Err(msg) => {
+                    return Err(
+                        ErrorResponse::new(
+                            format!(
+                                "Could not read pattern file {}: {}", 
+                                file_name, 
+                                msg
+                            ),
+                            PscanError::FileRead
+                        )
+                    )
+                }
+            };
+            patterns.extend(
+                contents
+                    .lines()
+                    .filter(|line| !line.is_empty())
+                    .map(String::from)
+            );
+        }
+        Ok(patterns)
+    }
+
+    // Appends the line with each span wrapped in the colour `color` picks
+    // for it
+    fn push_highlighted(
+        message_text: &mut String,
+        line: &str,
+        spans: &[Span],
+        color: impl Fn(&Span) -> &'static str
+    ) {
+        let mut last_idx: usize = 0;
+        for span in spans {
+            message_text.push_str(&line[last_idx..span.start]);
+            message_text.push_str(color(span));
+            message_text.push_str(&line[span.start..span.end]);
+            message_text.push_str("\x1b[0m");
+            last_idx = span.end;
+        }
+        message_text.push_str(&line[last_idx..]);
+    }
+
     fn highlight_matches(
         parsed_args: &ParsedArgs,
         file_name: String,
-        matches: Vec<(usize, String, usize, usize)>
+        matches: Vec<MatchedLine>
     ) -> usize {
 
         let mut start_msg: String = format!(
@@ -127,40 +335,85 @@ fn process_paths_from_args(
             file_name 
         );
 
-        if parsed_args.count_only {
-            let spaces: String = " ".repeat(
-                min(50, 50 - format!("{}", file_name).len())
-            ); 
-            start_msg.push_str(&format!(" {}-> {}", spaces, matches.len())); 
+        let count: usize = match parsed_args.count_matches {
+            true if !parsed_args.invert_match => {
+                matches.iter().map(|m| m.spans.len() - usize::from(m.continues)).sum()
+            },
+            _ => matches.iter().filter(|m| m.is_match).count()
+        };
+
+        if parsed_args.count_only || parsed_args.count_matches {
+            let spaces: String = " ".repeat(50usize.saturating_sub(file_name.len())); 
+            start_msg.push_str(&format!(" {}-> {}", spaces, count)); 
             println!("{start_msg}");
-            return matches.len()
+            return count
         };
                 
         println!("\x1b[32mMatches in {start_msg}"); 
  
         let max_num_spaces: usize = match matches.last() {
-            Some(x) => x.0.to_string().len(),
+            Some(x) => x.line_num.to_string().len(),
             None => 0 
         };
 
         let mut last_line_num: usize = 0;
+        let mut last_scope: Option<usize> = None;
 
-        for line_of_text in &matches {
+        for (idx, line_of_text) in matches.iter().enumerate() {
 
             let mut message_text: String = String::new();
             
-            let line_num = line_of_text.0;
-            let line = &line_of_text.1;
-            let match_start = line_of_text.2;
-            let match_end = line_of_text.3;
+            let line_num = line_of_text.line_num;
+            let line = &line_of_text.line;
            
             let num_spaces: usize = line_num.to_string().len();
             let padding: String = " ".repeat(max_num_spaces - num_spaces + 1); 
 
+            // With -o each match gets a line of its own, and there is no
+            // context to show
+            if parsed_args.only_matching {
+                for span in &line_of_text.spans {
+                    let color = match parsed_args.distinct_colors {
+                        true => PATTERN_COLORS[span.pattern % PATTERN_COLORS.len()],
+                        false => PATTERN_COLORS[0]
+                    };
+                    println!(
+                        "\x1b[36m{}{}| \x1b[0m{}{}\x1b[0m", 
+                        padding, 
+                        line_num, 
+                        color, 
+                        &line[span.start..span.end]
+                    );
+                }
+                continue
+            }
+
+            let group_start = last_line_num == 0 || line_num - last_line_num > 1;
             if last_line_num > 0 && line_num - last_line_num > 1 {
                 message_text.push_str("\x1b[1;35m ...\x1b[0m\n");
             };
 
+            // Each group of lines is headed by the definition its first
+            // match is in, unless the group before was in it too
+            if group_start {
+                let scope = matches[idx..]
+                    .iter()
+                    .find(|m| m.is_match)
+                    .and_then(|m| m.scope.as_ref());
+                if let Some(header) = scope
+                    && last_scope != Some(header.line_num) {
+                    message_text.push_str(
+                        &format!(
+                            "\x1b[1;34m{}@ {}\x1b[0m\x1b[2m  (line {})\x1b[0m\n", 
+                            padding,
+                            header.text,
+                            header.line_num
+                        )
+                    );
+                }
+                last_scope = scope.map(|h| h.line_num);
+            }
+
             message_text.push_str(
                 &format!(
                     "\x1b[36m{}{}| \x1b[0m", 
@@ -169,19 +422,37 @@ fn process_paths_from_args(
                 )
             );
 
-            message_text.push_str(&line[0..match_start]);
-            let matched_word = &line[match_start .. match_end];
+            // With --replace, a match is shown as the line before and
+            // after, with the old text in red and the new in green
+            if let Some((new_line, new_spans)) = &line_of_text.replacement {
+                message_text.push_str("\x1b[31m- \x1b[0m");
+                push_highlighted(&mut message_text, line, &line_of_text.spans, |_| REMOVED);
+                message_text.push_str(
+                    &format!("\n\x1b[36m{}{}| \x1b[0m\x1b[32m+ \x1b[0m", padding, line_num)
+                );
+                push_highlighted(&mut message_text, new_line, new_spans, |_| ADDED);
+            } else {
+                if parsed_args.replace.is_some() {
+                    message_text.push_str("  ");
+                }
+                push_highlighted(&mut message_text, line, &line_of_text.spans, |span| {
+                    match parsed_args.distinct_colors {
+                        true => PATTERN_COLORS[span.pattern % PATTERN_COLORS.len()],
+                        false => PATTERN_COLORS[0]
+                    }
+                });
+            }
 
-            message_text.push_str("\x1b[1;33m"); // yellow
-            message_text.push_str(matched_word);
-            message_text.push_str("\x1b[0m");
-            message_text.push_str(&line[match_end..]);
+            if parsed_args.fuzzy.is_some() 
+                && let Some(d) = line_of_text.spans.iter().map(|s| s.distance).min() {
+                message_text.push_str(&format!("\x1b[2m  (distance {})\x1b[0m", d));
+            }
             println!("{message_text}");
        
             last_line_num = line_num;
         }
-        println!(""); 
-        matches.len()
+        println!(); 
+        count
     }
     
     fn is_binary(reader: &mut BufReader<fs::File>) -> std::io::Result<bool> {
@@ -190,73 +461,209 @@ fn process_paths_from_args(
         Ok(buf.contains(&0))
     }
 
-    fn search<'a>(
-        query: &str, 
-        reader: &mut BufReader<fs::File>,
+    // With --multiline or --call, matches are found in the whole file at
+    // once and then cut into the part of each line they cover, so a match
+    // spanning three lines is highlighted on all three
+    fn search_buffer(
+        spans: &[Span],
+        text: &str,
+        trim: bool
+    ) -> Vec<(usize, String, Vec<Span>, bool, bool)> {
+
+        let mut results = Vec::new();
+        let mut first: usize = 0;  // spans before this ended on earlier lines
+        let mut line_start: usize = 0;
+
+        for (idx, raw) in text.split_inclusive('\n').enumerate() {
+
+            let content = raw.strip_suffix('\n').unwrap_or(raw);
+            let content = content.strip_suffix('\r').unwrap_or(content);
+            let line_end = line_start + content.len();
+            let next_start = line_start + raw.len();
+
+            let (line, offset) = match trim {
+                true => (content.trim(), content.len() - content.trim_start().len()),
+                false => (content, 0)
+            };
+
+            let mut found = false;
+            let mut continues = false;
+            let mut line_spans: Vec<Span> = Vec::new();
+
+            for span in spans[first..].iter().take_while(|s| s.start <= line_end) {
+                if span.start < line_start && span.end <= line_start {
+                    continue
+                }
+                found = true;
+                let start = span.start.max(line_start) - line_start;
+                let end = span.end.min(line_end) - line_start;
+                let start = start.saturating_sub(offset).min(line.len());
+                let end = end.saturating_sub(offset).min(line.len());
+                if start < end {
+                    continues |= line_spans.is_empty() && span.start < line_start;
+                    line_spans.push(Span { start, end, ..*span });
+                }
+            }
+
+            while first < spans.len() 
+                && spans[first].start < next_start 
+                && spans[first].end <= next_start {
+                first += 1;
+            }
+
+            results.push((idx + 1, line.to_string(), line_spans, found, continues));
+            line_start = next_start;
+        }
+        results
+    }
+
+    fn search(
+        matcher: &Matcher, 
+        reader: &mut impl BufRead,
+        extension: &str,
         parsed_args: &ParsedArgs
-    ) -> Vec<(usize, String, usize, usize)> {
+    ) -> Vec<MatchedLine> {
 
         let mut after_context = 0;
-        let mut before_context:
-            VecDeque<(usize, String, usize, usize)> = VecDeque::new();
+        let mut before_context: VecDeque<MatchedLine> = VecDeque::new();
 
         let mut matching_phrases = Vec::new(); 
-        
-        for (idx, line) in reader.lines().enumerate() {
 
-            let line = match line {
-                Ok(l) => {
-                    match parsed_args.trim {
-                        true => l.trim().to_string(),
-                        false => l 
-                    }
+        // With --code-only and the like, only matches in that part of the
+        // source count. Files the lexer can't read are skipped.
+        let mut lexer = match parsed_args.only_region {
+            Some(_) => match Lexer::for_extension(extension) {
+                Some(l) => Some(l),
+                None => return matching_phrases
+            },
+            None => None
+        };
+        let mut scopes = match parsed_args.scope {
+            true => ScopeTracker::for_extension(extension),
+            false => None
+        };
+
+        type Found = (usize, String, Vec<Span>, bool, bool);
+
+        // --call matches over lines like -U, and its replacements can too
+        let mut call_replacements: HashMap<usize, (String, Vec<Span>)> = HashMap::new();
+
+        let found: Box<dyn Iterator<Item = Found>> = if parsed_args.multiline 
+            || parsed_args.call.is_some() {
+            let mut text = String::new();
+            if reader.read_to_string(&mut text).is_err() {
+                return matching_phrases
+            }
+            let spans = match parsed_args.call {
+                Some(name) => match Lexer::for_extension(extension) {
+                    Some(mut call_lexer) => calls::find_calls(&text, &mut call_lexer, name),
+                    None => return matching_phrases
                 },
-                Err(_) => continue
+                None => matcher.find_iter(&text)
             };
+            if let Some(template) = parsed_args.replace
+                && parsed_args.call.is_some() {
+                call_replacements = calls::call_replacements(
+                    &text, &spans, template, parsed_args.trim
+                );
+            }
+            Box::new(search_buffer(&spans, &text, parsed_args.trim).into_iter())
+        } else {
+            let lines = reader.lines().enumerate().filter_map(|(idx, line)| {
+                match line {
+                    Ok(l) => match parsed_args.trim {
+                        true => Some((idx + 1, l.trim().to_string())),
+                        false => Some((idx + 1, l))
+                    },
+                    Err(_) => None
+                }
+            });
 
-            let (
-                has_match, 
-                start_idx, 
-                end_idx
-            ) = match parsed_args.case_sensitive {
+            // A boolean query can depend on other lines (NEAR/n, 
+            // --file-scope), so the whole file is read before any line is
+            // decided. Terms outside the --code-only region or the like
+            // are dropped before the query is, so the lexer is used up here.
+            match matcher.is_boolean() {
                 true => {
-                    let m = line.contains(query);
-                    let i = match line.find(query) {
-                        Some(v) => v,
-                        None => 0
+                    let (line_nums, lines): (Vec<usize>, Vec<String>) = lines.unzip();
+                    let regions: Option<Vec<_>> = lexer.take().map(|mut lex| {
+                        lines.iter().map(|line| lex.regions(line)).collect()
+                    });
+                    let keep = |idx: usize, s: &Span| match (&regions, parsed_args.only_region) {
+                        (Some(r), Some(region)) => lexer::within(&r[idx], s.start, s.end, region),
+                        _ => true
                     };
-                    let e = i + query.len();
-                    (m, i, e)
+                    let results = matcher.find_lines(&lines, parsed_args.file_scope, keep);
+                    Box::new(
+                        line_nums.into_iter()
+                            .zip(lines)
+                            .zip(results)
+                            .map(|((num, line), (spans, found))| (num, line, spans, found, false))
+                    )
                 },
-                false => {
-                    let lower_line = line.to_lowercase();
-                    let lower_q = &query.to_lowercase();
-                    let m = lower_line.contains(lower_q);
-                    let i = match lower_line.find(lower_q) {
-                        Some(v) => v,
-                        None => 0
-                    };
-                    let e = i + query.len();
-                    (m, i, e) 
+                false => Box::new(lines.map(|(num, line)| {
+                    let spans = matcher.find_iter(&line);
+                    let found = !spans.is_empty();
+                    (num, line, spans, found, false)
+                }))
+            }
+        };
+        
+        for (line_num, line, mut spans, mut found, mut continues) in found {
+
+            if let Some(lex) = &mut lexer
+                && let Some(region) = parsed_args.only_region {
+                let regions = lex.regions(&line);
+                let had_spans = !spans.is_empty();
+                let first = spans.first().copied();
+                spans.retain(|s| lexer::within(&regions, s.start, s.end, region));
+                if had_spans && spans.is_empty() {
+                    found = false;
                 }
+                continues &= spans.first().copied() == first;
+            }
+
+            let is_match = found != parsed_args.invert_match;
+            if !is_match || parsed_args.invert_match {
+                spans.clear();
+                continues = false;
+            }
+            let replacement = match parsed_args.replace {
+                Some(_) if parsed_args.call.is_some() && !spans.is_empty() => {
+                    call_replacements.remove(&line_num)
+                },
+                Some(template) if !spans.is_empty() => {
+                    Some(matcher.replace_line(&line, &spans, template))
+                },
+                _ => None
+            };
+            let scope = scopes.as_mut().and_then(|s| s.advance(line_num, &line));
+            let matched_line = MatchedLine { 
+                line_num, 
+                line, 
+                spans, 
+                continues,
+                is_match,
+                replacement,
+                scope
             };
 
-            if has_match {
+            if matched_line.is_match {
                 for _ in 0..before_context.len() {
                     if let Some(r) = before_context.pop_front() {
                         matching_phrases.push(r)
                     };
                 };
-                matching_phrases.push((idx + 1, line, start_idx, end_idx));
+                matching_phrases.push(matched_line);
                 after_context = parsed_args.context_after;
             
             } else {
                 if after_context > 0 {
-                    matching_phrases.push((idx + 1, line, 0, 0));
+                    matching_phrases.push(matched_line);
                     after_context -= 1;
                 }
                 else if parsed_args.context_before > 0 {
-                    before_context.push_back((idx + 1, line, 0, 0));
+                    before_context.push_back(matched_line);
                     if before_context.len() > parsed_args.context_before {
                         before_context.pop_front();
                     }
@@ -267,16 +674,183 @@ fn process_paths_from_args(
         matching_phrases
     }
 
+    // For --interactive: shows each replacement on its own, between its
+    // context lines, and asks whether to make it. Returns the new text of
+    // every line with at least one replacement accepted.
+    fn confirm_replacements(
+        path: &Path,
+        matcher: &Matcher,
+        parsed_args: &ParsedArgs,
+        matches: &[MatchedLine],
+        confirmation: &mut Confirmation
+    ) -> HashMap<usize, Option<String>> {
+
+        let mut new_lines: HashMap<usize, Option<String>> = HashMap::new();
+        let template = match parsed_args.replace {
+            Some(t) => t,
+            None => return new_lines
+        };
+        let width: usize = matches.last().map_or(0, |m| m.line_num.to_string().len());
+        let print_line = |num: usize, marker: &str, text: &str| {
+            println!("\x1b[36m {:>width$}| \x1b[0m{}{}", num, marker, text);
+        };
+
+        for matched in matches.iter().filter(|m| m.replacement.is_some()) {
+
+            let mut accepted: Vec<Span> = Vec::new();
+
+            for span in &matched.spans {
+                if !confirmation.accept_all && !confirmation.quit {
+                    let (new_line, new_spans) = matcher.replace_line(
+                        &matched.line, &[*span], template
+                    );
+                    let mut old_text = String::new();
+                    push_highlighted(&mut old_text, &matched.line, &[*span], |_| REMOVED);
+                    let mut new_text = String::new();
+                    push_highlighted(&mut new_text, &new_line, &new_spans, |_| ADDED);
+
+                    println!("\n\x1b[1;4;35m{}:\x1b[0m", path.display());
+                    let before = matched.line_num.saturating_sub(parsed_args.context_before);
+                    let after = matched.line_num + parsed_args.context_after;
+                    for m in matches.iter().filter(|m| m.line_num >= before && m.line_num < matched.line_num) {
+                        print_line(m.line_num, "  ", &m.line);
+                    }
+                    print_line(matched.line_num, "\x1b[31m- \x1b[0m", &old_text);
+                    print_line(matched.line_num, "\x1b[32m+ \x1b[0m", &new_text);
+                    for m in matches.iter().filter(|m| m.line_num > matched.line_num && m.line_num <= after) {
+                        print_line(m.line_num, "  ", &m.line);
+                    }
+                }
+                if confirmation.ask(&mut std::io::stdin().lock()) {
+                    accepted.push(*span);
+                }
+            }
+
+            if !accepted.is_empty() {
+                let (new_line, _) = matcher.replace_line(&matched.line, &accepted, template);
+                new_lines.insert(matched.line_num, Some(new_line));
+            }
+        }
+        new_lines
+    }
+
+    fn write_replacements(
+        path: &Path, 
+        new_lines: &HashMap<usize, Option<String>>,
+        context: &mut RunContext
+    ) -> Result<(), ErrorResponse> {
+
+        // The change is journalled before the file is touched, so it can
+        // always be undone, and taken back out if the write fails so the
+        // rest of the run still can be. Both go to the file a symlink
+        // points at.
+        let result = fs::canonicalize(path).and_then(|real_path| {
+            let contents = fs::read_to_string(&real_path)?;
+            let new_contents = replace::rewrite_lines(&contents, new_lines);
+            let entry = journal::Entry::new(&real_path, &contents, &new_contents, new_lines);
+            let journal_path = context.journal.get_or_insert_with(|| {
+                journal::new_journal_path(&context.journal_dir)
+            });
+            let start = journal::record(journal_path, &entry)?;
+            replace::write_atomic(&real_path, &new_contents).inspect_err(|_| {
+                let _ = journal::truncate(journal_path, start);
+            })
+        });
+        match result {
+            Ok(_) => {
+                println!(
+                    "\x1b[32mReplaced {} line(s) in {}\x1b[0m", 
+                    new_lines.len(), 
+                    path.display()
+                );
+                Ok(())
+            },
+            Err(msg) => Err(
+                ErrorResponse::new(
+                    format!("Could not write {}: {}", path.display(), msg),
+                    PscanError::FileRead
+                )
+            )
+        }
+    }
+
+    // Notebooks are searched cell by cell rather than as the JSON they are
+    // stored as, each cell's lines numbered from 1. Replacements are only
+    // previewed, since the JSON isn't written back. A file that isn't a
+    // notebook, like a git-lfs pointer, is skipped with a warning rather
+    // than ending the search.
+    fn scan_notebook(
+        path: &Path,
+        matcher: &Matcher,
+        parsed_args: &ParsedArgs
+    ) -> Result<usize, ErrorResponse> {
+
+        let notebook = match fs::read_to_string(path).map(|t| Notebook::parse(&t)) {
+            Ok(Ok(n)) => n,
+            Ok(Err(msg)) => {
+                eprintln!(
+                    "\x1b[1;33mSkipping {}: not a valid notebook: {}\x1b[0m",
+                    path.display(),
+                    msg
+                );
+                return Ok(0)
+            },
+            Err(msg) => {
+                return Err(
+                    ErrorResponse::new(
+                        format!("File open failed: {}: {}", path.display(), msg),
+                        PscanError::FileRead
+                    )
+                )
+            }
+        };
+
+        let mut num_matches: usize = 0;
+        for cell in &notebook.cells {
+
+            let extension = match cell.kind.as_str() {
+                "code" => notebook.extension.as_str(),
+                "markdown" => "md",
+                _ => ""
+            };
+            let mut sections = vec![(format!("cell {}", cell.number), &cell.source, extension)];
+            if parsed_args.notebook_outputs && !cell.outputs.is_empty() {
+                sections.push((format!("cell {} output", cell.number), &cell.outputs, ""));
+            }
+
+            for (label, text, extension) in sections {
+                let matches = search(matcher, &mut text.as_bytes(), extension, parsed_args);
+                if !matches.is_empty() {
+                    let name = format!("{} [{}]", path.display(), label);
+                    num_matches += highlight_matches(parsed_args, name, matches);
+                }
+            }
+        }
+        Ok(num_matches)
+    }
+
     fn scan_file_for_matches(
         search_path: &Path,
-        parsed_args: &ParsedArgs
+        matcher: &Matcher,
+        parsed_args: &ParsedArgs,
+        filters: &Filters,
+        context: &mut RunContext
     ) -> Result<usize, ErrorResponse> {
 
-        if file_is_ignored(search_path, &parsed_args) {
+        if file_is_ignored(search_path, parsed_args, filters) {
             return Ok(0)    
         }; 
 
-        let file = match fs::File::open(&search_path) {
+        // The user has already quit an --interactive run
+        if parsed_args.interactive && context.confirmation.quit {
+            return Ok(0)
+        }
+
+        if get_extension(search_path) == "ipynb" {
+            return scan_notebook(search_path, matcher, parsed_args)
+        }
+
+        let file = match fs::File::open(search_path) {
             Ok(f) => f,
             Err(msg) => {
                 return Err(
@@ -294,30 +868,61 @@ fn process_paths_from_args(
 
         let mut reader: BufReader<_> = BufReader::new(file);
       
-        if let Ok(b) = is_binary(&mut reader) { 
-            if b  && parsed_args.binary_ok { return Ok(0) } 
+        if let Ok(b) = is_binary(&mut reader) 
+            && b && parsed_args.binary_ok { 
+            return Ok(0) 
         };
 
         let matches = search(
-            parsed_args.query, 
+            matcher, 
             &mut reader, 
+            &get_extension(search_path),
             parsed_args
         );
+
+        if parsed_args.interactive {
+            let new_lines = confirm_replacements(
+                search_path, matcher, parsed_args, &matches, &mut context.confirmation
+            );
+            if !new_lines.is_empty() {
+                write_replacements(search_path, &new_lines, context)?;
+            }
+            return Ok(matches.iter().filter(|m| m.is_match).count())
+        }
+
+        // A line that only held calls being removed is taken out
+        let new_lines: HashMap<usize, Option<String>> = matches
+            .iter()
+            .filter_map(|m| {
+                m.replacement.as_ref().map(|(new_line, _)| {
+                    let removed = parsed_args.call.is_some() && new_line.trim().is_empty();
+                    (m.line_num, (!removed).then(|| new_line.clone()))
+                })
+            })
+            .collect();
         
-        let num_matches = matches.len(); 
+        let mut num_matches: usize = 0; 
         
-        if num_matches > 0 {
+        if !matches.is_empty() {
             let file_name: String = format!("{}", &search_path.display());
-            highlight_matches(&parsed_args, file_name, matches);
+            num_matches = highlight_matches(parsed_args, file_name, matches);
         };
 
+        if parsed_args.write && !new_lines.is_empty() {
+            write_replacements(search_path, &new_lines, context)?;
+        }
+
         Ok(num_matches)
 
     }
 
     fn walk(
         scan_path: &Path, 
-        parsed_args: &ParsedArgs
+        matcher: &Matcher,
+        parsed_args: &ParsedArgs,
+        filters: &mut Filters,
+        context: &mut RunContext,
+        depth: usize
     ) -> Result<usize, ErrorResponse> {
         
         let mut total_matches_found: usize = 0;
@@ -327,13 +932,11 @@ fn process_paths_from_args(
             if let (Some(file_name), Some(excluded)) = (
                 &scan_path.file_name().and_then(OsStr::to_str),
                 &parsed_args.exclude_file_types.as_ref()
-            ) {
-                if excluded.contains(&file_name) {
-                    return Ok(0); 
-                }
+            ) && excluded.contains(file_name) {
+                return Ok(0); 
             };
             
-            let entries = match fs::read_dir(&scan_path) {
+            let entries = match fs::read_dir(scan_path) {
                 Ok(en) => en,
                 Err(msg) => {
                     
@@ -352,58 +955,67 @@ fn process_paths_from_args(
                 }
             };
             
-            for entry_result in entries {
+            let added = filters.ignores.enter(scan_path);
+
+            for entry_result in entries.flatten() {
                
-                if let Ok(entry_result) = entry_result {
-
-                    let this_path = entry_result.path();
-
-                    if this_path.is_dir() {
-                       
-                        let result = walk(&this_path, parsed_args);
-                        match result {
-                            Ok(i) => total_matches_found += i,
-                            Err(_) => {
-                                return result;
-                            }
+                let this_path = entry_result.path();
+                let is_dir = this_path.is_dir();
+
+                if !should_visit(&this_path, depth + 1, is_dir, parsed_args, filters) {
+                    continue
+                }
+
+                if is_dir {
+                   
+                    let result = walk(
+                        &this_path, matcher, parsed_args, filters, context, depth + 1
+                    );
+                    match result {
+                        Ok(i) => total_matches_found += i,
+                        Err(_) => {
+                            return result;
                         }
+                    }
 
-                    } else {
-                      
-                        let result = scan_file_for_matches(
-                            &this_path, 
-                            parsed_args
-                        );
-                       
-                        match result {
-                            Ok(i) => total_matches_found += i,
-                            Err(error) => {
-                                return Err(error)
-                            }
+                } else {
+                  
+                    let result = scan_file_for_matches(
+                        &this_path, 
+                        matcher,
+                        parsed_args,
+                        filters,
+                        context
+                    );
+                   
+                    match result {
+                        Ok(i) => total_matches_found += i,
+                        Err(error) => {
+                            return Err(error)
                         }
                     }
                 }
             }
+
+            filters.ignores.leave(added);
         
         } else if scan_path.is_file() {
             
             let ext = get_extension(&parsed_args.path);
             let ext_str: &str = &ext;
             
-            if let Some(e) = &parsed_args.exclude_file_types {
-                if ext.len() > 0 && e.contains(&ext_str) {
-                    return Ok(0) 
-                } 
+            if let Some(e) = &parsed_args.exclude_file_types 
+                && !ext.is_empty() && e.contains(&ext_str) {
+                return Ok(0) 
             };
 
-            let result = scan_file_for_matches(
-                &scan_path, 
-                parsed_args 
-            );
-
-            if let Err(_) = result {
-                return result 
-            }
+            scan_file_for_matches(
+                scan_path, 
+                matcher,
+                parsed_args,
+                filters,
+                context
+            )?;
         };
 
         Ok(total_matches_found)
@@ -413,12 +1025,50 @@ fn process_paths_from_args(
     //=======================================================================//
     // -------------------------- LOGIC STARTS HERE ------------------------ //
     //=======================================================================// 
+    let patterns = collect_patterns(parsed_args)?;
+    if patterns.is_empty() {
+        return Err(
+            ErrorResponse::new(
+                String::from("No patterns to search for"),
+                PscanError::InputError
+            )
+        )
+    }
+
+    let matcher = match Matcher::new(&patterns, parsed_args) {
+        Ok(m) => m,
+        Err(msg) => {
+            return Err(ErrorResponse::new(msg, PscanError::Pattern))
+        }
+    };
+
+    let globs = match GlobFilter::new(&parsed_args.globs) {
+        Ok(g) => g,
+        Err(msg) => {
+            return Err(ErrorResponse::new(msg, PscanError::Pattern))
+        }
+    };
+    let mut filters = Filters {
+        ignores: Ignores::none(),
+        globs,
+        types: type_filter(parsed_args)?,
+        journal_dir: journal::resolve_dir(&context.journal_dir)
+    };
+    if fs::canonicalize(&parsed_args.path).is_ok_and(|p| p.starts_with(&filters.journal_dir)) {
+        return Ok(0)
+    }
+
+    // Each run starts its own journal and asks afresh
+    context.journal = None;
+    context.confirmation = Confirmation::new();
+
     // Check for valid path and query
     if parsed_args.path.as_path().is_dir() {
       
         let mut total_matches_found: usize = 0;
 
-        if !&parsed_args.recursive {
+        // --max-depth is a limit on recursion, so it doesn't need -r
+        if !&parsed_args.recursive && parsed_args.max_depth.is_none() {
             let err_msg = String::from(
                 "Can't search paths without recursion enabled"
             );
@@ -430,32 +1080,38 @@ fn process_paths_from_args(
             );            
         }
 
+        if !parsed_args.no_ignore {
+            filters.ignores = Ignores::for_root(&parsed_args.path);
+        }
+
         match fs::read_dir(&parsed_args.path) {
             
             Ok(entries) => {
 
-                for entry_result in entries {
+                for entry in entries.flatten() {
                     
-                    if let Ok(entry) = entry_result {
-                        
-                        let this_path = entry.path();
-
-                        let result = if this_path.is_dir() {
-                            walk(&this_path, parsed_args)
-
-                        } else {
-
-                            scan_file_for_matches(&this_path, parsed_args)
-
-                        };
-                        
-                        match result {
-                            Ok(x) => { 
-                                total_matches_found += x; 
-                            },
-                            Err(err) => { 
-                                return Err(err) 
-                            }
+                    let this_path = entry.path();
+                    let is_dir = this_path.is_dir();
+
+                    if !should_visit(&this_path, 1, is_dir, parsed_args, &filters) {
+                        continue
+                    }
+
+                    let result = if is_dir {
+                        walk(&this_path, &matcher, parsed_args, &mut filters, context, 1)
+
+                    } else {
+
+                        scan_file_for_matches(&this_path, &matcher, parsed_args, &filters, context)
+
+                    };
+                    
+                    match result {
+                        Ok(x) => { 
+                            total_matches_found += x; 
+                        },
+                        Err(err) => { 
+                            return Err(err) 
                         }
                     }
                 };
@@ -478,7 +1134,7 @@ fn process_paths_from_args(
         }
 
     } else if parsed_args.path.as_path().is_file() {
-        scan_file_for_matches(&parsed_args.path, &parsed_args)
+        scan_file_for_matches(&parsed_args.path, &matcher, parsed_args, &filters, context)
     
     } else {
         Err(
@@ -491,20 +1147,65 @@ fn process_paths_from_args(
 }
 
 
-pub fn run(input_args: Option<Vec<String>>) -> Result<usize, ErrorResponse> {
+// Restores the files changed by the last run that wrote any, returning how
+// many there were
+fn undo_last_run(context: &RunContext) -> Result<usize, ErrorResponse> {
+
+    let last = match journal::last_journal(&context.journal_dir) {
+        Ok(Some(j)) => j,
+        Ok(None) => {
+            return Err(
+                ErrorResponse::new(
+                    String::from("There is no run to undo in this directory"),
+                    PscanError::InputError
+                )
+            )
+        },
+        Err(msg) => {
+            return Err(ErrorResponse::new(msg.to_string(), PscanError::FileRead))
+        }
+    };
+    match journal::undo(&last) {
+        Ok(restored) => {
+            for path in &restored {
+                println!("\x1b[32mRestored {}\x1b[0m", path.display());
+            }
+            Ok(restored.len())
+        },
+        Err(msg) => Err(ErrorResponse::new(msg, PscanError::FileRead))
+    }
+}
+
+
+pub fn run(
+    input_args: Option<Vec<String>>,
+    context: &mut RunContext
+) -> Result<usize, ErrorResponse> {
 
     // Verify correct input
-    let args: Vec<String> = match input_args {
-        Some(a) => a,
-        None => env::args().skip(1).collect()
+    let (args, config_len): (Vec<String>, usize) = match input_args {
+        Some(a) => (a, 0),
+        None => {
+            // Defaults from the config file go first so the command line
+            // can override them
+            let mut a = match arguments::load_config_args() {
+                Ok(c) => c,
+                Err(msg) => {
+                    return Err(ErrorResponse::new(msg, PscanError::FileRead))
+                }
+            };
+            let config_len = a.len();
+            a.extend(env::args().skip(1));
+            (a, config_len)
+        }
     }; 
   
-    let parsed_args = match arguments::parse(&args) {
+    let parsed_args = match arguments::parse(&args, config_len) {
         Ok(c) => c,
         Err(msg) => {
             return Err(
                 ErrorResponse {
-                    error_msg: format!("{}", msg),
+                    error_msg: msg.to_string(),
                     error_type: PscanError::InputError 
                 }
             )
@@ -515,10 +1216,20 @@ pub fn run(input_args: Option<Vec<String>>) -> Result<usize, ErrorResponse> {
         println!("{HELP_TEXT}");
         return Ok(0)
     } 
+    else if parsed_args.undo {
+        return undo_last_run(context)
+    }
+    else if parsed_args.type_list {
+        match Types::new(&parsed_args.type_adds) {
+            Ok(types) => println!("{}", types.list()),
+            Err(msg) => return Err(ErrorResponse::new(msg, PscanError::Argument))
+        }
+        return Ok(0)
+    }
     else if parsed_args.show_args {
         println!("{}", parsed_args);
     };
-    process_paths_from_args(&parsed_args)
+    process_paths_from_args(&parsed_args, context)
 
 }
 
@@ -534,7 +1245,7 @@ mod tests {
     fn try_a_directory_without_recursion() {
         let search_path: String = String::from("src/text_files");
         let search_query: String = String::from("mary");
-        let status = run(Some(vec![search_path, search_query])).is_ok();
+        let status = run(Some(vec![search_path, search_query]), &mut RunContext::new()).is_ok();
         assert!(status);
     }
 
@@ -546,12 +1257,7 @@ mod tests {
         let input_args = Some(vec![
             params, search_path, search_query
         ]);
-        let result = match run(input_args) {
-            Ok(matches) => {
-                matches
-            },
-            Err(_) => 0
-        };
+        let result = run(input_args, &mut RunContext::new()).unwrap_or_default();
         assert!(result > 0); 
     }
 
@@ -561,13 +1267,554 @@ mod tests {
         let search_path: String = String::from("src/text_files/mary.txt");
         let search_query: String = String::from("mary");
         let input_args = Some(vec![params, search_path, search_query]);
-        let result = match run(input_args) {
-            Ok(x) => x,
-            Err(_) => 0
-        };
+        let result = run(input_args, &mut RunContext::new()).unwrap_or_default();
         assert!(result > 0); 
     }
 
+    #[test]
+    fn regex_test() {
+        let input_args = Some(vec![
+            "-P".to_string(),
+            "src/text_files/mary.txt".to_string(),
+            r"^It\s\w+".to_string()
+        ]);
+        let result = run(input_args, &mut RunContext::new()).unwrap_or_default();
+        assert_eq!(result, 2); 
+    }
+
+    #[test]
+    fn count_lines_and_occurrences_test() {
+        let lines = run(Some(vec![
+            "-c".to_string(),
+            "src/text_files/humpty.txt".to_string(),
+            "umpty".to_string()
+        ]), &mut RunContext::new()).unwrap_or_default();
+        let occurrences = run(Some(vec![
+            "--count-matches".to_string(),
+            "src/text_files/humpty.txt".to_string(),
+            "umpty".to_string()
+        ]), &mut RunContext::new()).unwrap_or_default();
+        assert_eq!(lines, 3); 
+        assert_eq!(occurrences, 5); 
+    }
+
+    #[test]
+    fn long_file_name_count_test() {
+        let root = env::temp_dir().join("pscan_long_name_test");
+        let _ = fs::create_dir_all(&root);
+        let path = root.join(format!("{}.txt", "a".repeat(60)));
+        let _ = fs::write(&path, "lamb\n");
+        let input_args = Some(vec!["-c".to_string(), path.display().to_string(), "lamb".to_string()]);
+        let result = run(input_args, &mut RunContext::new());
+        let _ = fs::remove_dir_all(&root);
+        assert_eq!(result.ok(), Some(1));
+    }
+
+    #[test]
+    fn multiple_patterns_test() {
+        let input_args = Some(vec![
+            "-c".to_string(),
+            "-e".to_string(),
+            "lamb".to_string(),
+            "-e".to_string(),
+            "school".to_string(),
+            "src/text_files/mary.txt".to_string()
+        ]);
+        let result = run(input_args, &mut RunContext::new()).unwrap_or_default();
+        assert_eq!(result, 4); 
+
+        let input_args = Some(vec![
+            "--count-matches".to_string(),
+            "-f".to_string(),
+            "src/text_files/patterns.txt".to_string(),
+            "src/text_files/humpty.txt".to_string()
+        ]);
+        let result = run(input_args, &mut RunContext::new()).unwrap_or_default();
+        assert_eq!(result, 5); 
+    }
+
+    #[test]
+    fn invert_match_test() {
+        let input_args = Some(vec![
+            "-vc".to_string(),
+            "src/text_files/humpty.txt".to_string(),
+            "Humpty".to_string()
+        ]);
+        let result = run(input_args, &mut RunContext::new()).unwrap_or_default();
+        assert_eq!(result, 1);
+    }
+
+    #[test]
+    fn capture_group_test() {
+        let input_args = Some(vec![
+            "-oP".to_string(),
+            "--count-matches".to_string(),
+            "--capture".to_string(),
+            "who".to_string(),
+            "src/text_files/mary.txt".to_string(),
+            "(?P<who>Mary )?had|lamb".to_string()
+        ]);
+        let result = run(input_args, &mut RunContext::new()).unwrap_or_default();
+        assert_eq!(result, 1);
+
+        let input_args = Some(vec![
+            "-P".to_string(),
+            "--capture".to_string(),
+            "2".to_string(),
+            "src/text_files/mary.txt".to_string(),
+            "(lamb)".to_string()
+        ]);
+        assert!(run(input_args, &mut RunContext::new()).is_err());
+    }
+
+    #[test]
+    fn replace_and_write_test() {
+        let path = env::temp_dir().join("pscan_replace_test.txt");
+        let _ = fs::copy("src/text_files/humpty.txt", &path);
+
+        let input_args = Some(vec![
+            "-P".to_string(),
+            "--replace".to_string(),
+            "D${rest}".to_string(),
+            "--write".to_string(),
+            path.display().to_string(),
+            r"H(?P<rest>umpty)".to_string()
+        ]);
+        let journal_dir = env::temp_dir().join("pscan_replace_test_journal");
+        let result = run(input_args, &mut RunContext::with_journal_dir(&journal_dir))
+            .unwrap_or_default();
+        assert_eq!(result, 3);
+
+        let contents = fs::read_to_string(&path).unwrap_or_default();
+        assert!(contents.starts_with("Dumpty Dumpty sat on a wall,\n"));
+        assert!(!contents.contains("Humpty"));
+        assert_eq!(contents.lines().count(), 4);
+
+        let undo_args = Some(vec!["--undo".to_string()]);
+        let result = run(undo_args, &mut RunContext::with_journal_dir(&journal_dir))
+            .unwrap_or_default();
+        let contents = fs::read_to_string(&path).unwrap_or_default();
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_dir_all(&journal_dir);
+        assert_eq!(result, 1);
+        assert_eq!(contents, fs::read_to_string("src/text_files/humpty.txt").unwrap_or_default());
+    }
+
+    #[test]
+    fn failed_write_test() {
+        let dir = env::temp_dir().join(format!("pscan_failed_write_{}", std::process::id()));
+        let journal_dir = dir.join("journal");
+        fs::create_dir_all(&dir).expect("create test dir");
+        fs::write(dir.join("a.txt"), "lamb\n").expect("write a.txt");
+        fs::write(dir.join("b.txt"), "lamb\n").expect("write b.txt");
+        // The temporary file b.txt is written to can't be created
+        fs::create_dir_all(dir.join(".b.txt.pscan-tmp")).expect("block b.txt");
+
+        let mut context = RunContext::with_journal_dir(&journal_dir);
+        let replace = |file: &str, context: &mut RunContext| {
+            let args = ["--replace", "sheep", "--write"];
+            let mut input_args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+            input_args.push(dir.join(file).display().to_string());
+            input_args.push("lamb".to_string());
+            run(Some(input_args), context)
+        };
+        assert!(replace("a.txt", &mut context).is_ok());
+        assert!(replace("b.txt", &mut context).is_err());
+
+        // The run is undone as far as it got
+        let undo_args = Some(vec!["--undo".to_string()]);
+        let result = run(undo_args, &mut RunContext::with_journal_dir(&journal_dir));
+        let a = fs::read_to_string(dir.join("a.txt")).unwrap_or_default();
+        let b = fs::read_to_string(dir.join("b.txt")).unwrap_or_default();
+        let _ = fs::remove_dir_all(&dir);
+        assert_eq!(result.unwrap_or_default(), 1);
+        assert_eq!(a, "lamb\n");
+        assert_eq!(b, "lamb\n");
+    }
+
+    #[test]
+    fn journal_is_never_searched() {
+        let root = env::temp_dir().join("pscan_journal_walk_test");
+        let _ = fs::create_dir_all(&root);
+        let _ = fs::write(root.join("notes.txt"), "lamb\n");
+        let journal_dir = root.join(".pscan/journal");
+
+        // The second run would find "lamb" in the first run's journal
+        let replace = || {
+            let args = ["-r", "--hidden", "--no-ignore", "--replace", "sheep", "--write"];
+            let mut input_args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+            input_args.push(root.display().to_string());
+            input_args.push("lamb".to_string());
+            run(Some(input_args), &mut RunContext::with_journal_dir(&journal_dir))
+                .unwrap_or_default()
+        };
+        let first = replace();
+        let second = replace();
+        let undo_args = Some(vec!["--undo".to_string()]);
+        let undone = run(undo_args, &mut RunContext::with_journal_dir(&journal_dir))
+            .unwrap_or_default();
+        let contents = fs::read_to_string(root.join("notes.txt")).unwrap_or_default();
+        let _ = fs::remove_dir_all(&root);
+        assert_eq!((first, second, undone), (1, 0, 1));
+        assert_eq!(contents, "lamb\n");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn write_through_symlink_test() {
+        let root = env::temp_dir().join("pscan_symlink_write_test");
+        let _ = fs::create_dir_all(&root);
+        let _ = fs::write(root.join("real.txt"), "lamb\n");
+        let _ = std::os::unix::fs::symlink(root.join("real.txt"), root.join("link.txt"));
+        let journal_dir = root.join("journal");
+
+        let input_args = Some(vec![
+            "--replace".to_string(),
+            "sheep".to_string(),
+            "--write".to_string(),
+            root.join("link.txt").display().to_string(),
+            "lamb".to_string()
+        ]);
+        run(input_args, &mut RunContext::with_journal_dir(&journal_dir)).unwrap_or_default();
+        let written = fs::read_to_string(root.join("real.txt")).unwrap_or_default();
+        let still_link = fs::symlink_metadata(root.join("link.txt"))
+            .is_ok_and(|m| m.file_type().is_symlink());
+
+        let undo_args = Some(vec!["--undo".to_string()]);
+        let undone = run(undo_args, &mut RunContext::with_journal_dir(&journal_dir))
+            .unwrap_or_default();
+        let restored = fs::read_to_string(root.join("real.txt")).unwrap_or_default();
+        let _ = fs::remove_dir_all(&root);
+        assert_eq!(written, "sheep\n");
+        assert!(still_link);
+        assert_eq!(undone, 1);
+        assert_eq!(restored, "lamb\n");
+    }
+
+    #[test]
+    fn code_only_test() {
+        let count = |only: &str| {
+            let input_args = Some(vec![
+                "-c".to_string(),
+                only.to_string(),
+                "src/text_files/code/debug.py".to_string(),
+                "print".to_string()
+            ]);
+            run(input_args, &mut RunContext::new()).unwrap_or_default()
+        };
+        assert_eq!(count("--code-only"), 2);
+        assert_eq!(count("--comments-only"), 3);
+        assert_eq!(count("--strings-only"), 2);
+        assert_eq!(count("--case-sensitive"), 6);
+    }
+
+    #[test]
+    fn call_test() {
+        let input_args = Some(vec![
+            "-c".to_string(),
+            "--call".to_string(),
+            "print".to_string(),
+            "src/text_files/code/debug.py".to_string()
+        ]);
+        assert_eq!(run(input_args, &mut RunContext::new()).unwrap_or_default(), 4);
+
+        // A call over three lines is highlighted on each but counted once
+        let input_args = Some(vec![
+            "--call".to_string(),
+            "print".to_string(),
+            "--replace".to_string(),
+            "pass".to_string(),
+            "--count-matches".to_string(),
+            "src/text_files/code/debug.py".to_string()
+        ]);
+        assert_eq!(run(input_args, &mut RunContext::new()).unwrap_or_default(), 2);
+    }
+
+    #[test]
+    fn call_removal_test() {
+        let path = env::temp_dir().join("pscan_call_removal_test.py");
+        let original = fs::read_to_string("src/text_files/code/debug.py").unwrap_or_default();
+        let _ = fs::write(&path, &original);
+        let journal_dir = env::temp_dir().join("pscan_call_removal_test_journal");
+
+        let input_args = Some(vec![
+            "--call".to_string(),
+            "print".to_string(),
+            "--replace".to_string(),
+            "".to_string(),
+            "--write".to_string(),
+            path.display().to_string()
+        ]);
+        let result = run(input_args, &mut RunContext::with_journal_dir(&journal_dir));
+        assert_eq!(result.unwrap_or_default(), 4);
+
+        // The call over three lines goes and leaves no blank lines behind,
+        // the one with a comment after it keeps the comment
+        let contents = fs::read_to_string(&path).unwrap_or_default();
+        assert_eq!(contents.lines().count(), original.lines().count() - 3);
+        assert!(contents.contains("def greet(name):\n      # print the greeting\n"));
+        assert!(contents.ends_with("    def log(self, text):\n"));
+
+        let undo_args = Some(vec!["--undo".to_string()]);
+        let result = run(undo_args, &mut RunContext::with_journal_dir(&journal_dir));
+        let contents = fs::read_to_string(&path).unwrap_or_default();
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_dir_all(&journal_dir);
+        assert_eq!(result.unwrap_or_default(), 1);
+        assert_eq!(contents, original);
+    }
+
+    #[test]
+    fn notebook_test() {
+        let count = |flag: &str, query: &str| {
+            let input_args = Some(vec![
+                "-c".to_string(),
+                flag.to_string(),
+                "src/text_files/code/analysis.ipynb".to_string(),
+                query.to_string()
+            ]);
+            run(input_args, &mut RunContext::new()).unwrap_or_default()
+        };
+        assert_eq!(count("--case-sensitive", "print"), 4);
+        assert_eq!(count("--code-only", "print"), 2);
+        assert_eq!(count("--case-sensitive", "summary"), 2);
+        assert_eq!(count("--notebook-outputs", "summary"), 3);
+    }
+
+    #[test]
+    fn invalid_notebook_test() {
+        let root = env::temp_dir().join("pscan_invalid_notebook_test");
+        let _ = fs::create_dir_all(&root);
+        let _ = fs::copy("src/text_files/code/analysis.ipynb", root.join("good.ipynb"));
+        let _ = fs::write(
+            root.join("lfs.ipynb"),
+            "version https://git-lfs.github.com/spec/v1\noid sha256:4d7a\nsize 1024\n"
+        );
+        let _ = fs::write(root.join("truncated.ipynb"), "{\"cells\": [{\"source\": \"print(");
+        let _ = fs::write(root.join("nested.ipynb"), "[".repeat(100_000));
+
+        let input_args = Some(vec![
+            "-rc".to_string(),
+            root.display().to_string(),
+            "print(".to_string()
+        ]);
+        let result = run(input_args, &mut RunContext::new());
+        let _ = fs::remove_dir_all(&root);
+        assert_eq!(result.ok(), Some(3));
+    }
+
+    // A directory of files for a test, named after it and the process so
+    // runs that overlap don't share it
+    fn test_dir(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let root = env::temp_dir().join(format!("pscan_{}_{}", name, std::process::id()));
+        for (file, text) in files {
+            let path = root.join(file);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent).expect("create test directory");
+            }
+            fs::write(&path, text).expect("write test file");
+        }
+        root
+    }
+
+    fn count_in(path: &Path, options: &[&str], query: &str) -> Result<usize, ErrorResponse> {
+        let mut input_args: Vec<String> = options.iter().map(|a| a.to_string()).collect();
+        input_args.push(path.display().to_string());
+        input_args.push(query.to_string());
+        run(Some(input_args), &mut RunContext::new())
+    }
+
+    #[test]
+    fn ignore_files_test() {
+        let root = test_dir("ignore_test", &[
+            (".gitignore", "target/\n*.log\n"),
+            ("src/.ignore", "!keep.log\n"),
+            ("target/debug/out.txt", "lamb\n"),
+            ("src/main.txt", "lamb\n"),
+            ("src/run.log", "lamb\n"),
+            ("src/keep.log", "lamb\n")
+        ]);
+        let searched = count_in(&root, &["-rc"], "lamb");
+        let everything = count_in(&root, &["-rc", "--no-ignore"], "lamb");
+        let _ = fs::remove_dir_all(&root);
+        assert_eq!(searched.ok(), Some(2));
+        assert_eq!(everything.ok(), Some(4));
+    }
+
+    #[test]
+    fn hidden_files_test() {
+        let root = test_dir("hidden_test", &[
+            (".git/objects/ab12", "lamb\n"),
+            (".notes.txt.swp", "lamb\n"),
+            ("notes.txt", "lamb\n")
+        ]);
+        let searched = count_in(&root, &["-rc"], "lamb");
+        let everything = count_in(&root, &["-rc", "--hidden"], "lamb");
+        let _ = fs::remove_dir_all(&root);
+        assert_eq!(searched.ok(), Some(1));
+        assert_eq!(everything.ok(), Some(3));
+    }
+
+    #[test]
+    fn file_types_test() {
+        let root = test_dir("types_test", &[
+            ("deploy", "#!/usr/bin/env python3\nprint(1)\n"),
+            ("app.py", "print(2)\n"),
+            ("Makefile", "print:\n"),
+            ("notes.txt", "print\n")
+        ]);
+        let count = |options: &[&str]| {
+            let mut with_recursion = vec!["-rc"];
+            with_recursion.extend(options);
+            count_in(&root, &with_recursion, "print")
+        };
+        let python = count(&["-t", "py"]);
+        let make = count(&["--type", "make"]);
+        let not_python = count(&["-T", "py"]);
+        let added = count(&["--type-add", "notes:*.txt,Makefile", "-t", "notes"]);
+        let unknown = count(&["-t", "cobol"]);
+        let _ = fs::remove_dir_all(&root);
+        assert_eq!(python.ok(), Some(2));
+        assert_eq!(make.ok(), Some(1));
+        assert_eq!(not_python.ok(), Some(2));
+        assert_eq!(added.ok(), Some(2));
+        assert!(unknown.is_err());
+    }
+
+    #[test]
+    fn depth_test() {
+        let count = |options: &[&str]| {
+            let mut with_count = vec!["-c"];
+            with_count.extend(options);
+            count_in(Path::new("src/text_files"), &with_count, "the")
+        };
+        let all = count(&["-r"]).unwrap_or_default();
+        let top = count(&["--max-depth", "1"]).unwrap_or_default();
+        let nested = count(&["-r", "--min-depth", "2"]).unwrap_or_default();
+        assert!(top > 0 && nested > 0);
+        assert_eq!(top + nested, all);
+        assert_eq!(count(&["--max-depth", "2", "--min-depth", "2"]).ok(), Some(nested));
+        assert!(count(&["--max-depth", "1", "--min-depth", "2"]).is_err());
+    }
+
+    #[test]
+    fn path_glob_test() {
+        let count = |globs: &[&str]| {
+            let mut options = vec!["-rc"];
+            for g in globs {
+                options.extend(["-g", g]);
+            }
+            count_in(Path::new("src/text_files"), &options, "the").unwrap_or_default()
+        };
+        let all = count(&[]);
+        let notes = count(&["notes/**"]);
+        assert!(notes > 0 && notes < all);
+        assert_eq!(count(&["*.txt", "!notes"]) + notes, count(&["*.txt"]));
+        assert_eq!(count(&["!**/*.txt"]), all - count(&["*.txt"]));
+    }
+
+    #[test]
+    fn glob_query_test() {
+        let count = |query: &str| {
+            let input_args = Some(vec![
+                "-c".to_string(),
+                "--glob-query".to_string(),
+                "src/text_files/mary.txt".to_string(),
+                query.to_string()
+            ]);
+            run(input_args, &mut RunContext::new()).unwrap_or_default()
+        };
+        assert_eq!(count("It *e the"), 1);
+        assert_eq!(count("l?mb*sc[!x]ool"), 1);
+        assert_eq!(count("[MT]*a"), 5);
+    }
+
+    #[test]
+    fn fuzzy_test() {
+        let input_args = Some(vec![
+            "-c".to_string(),
+            "--fuzzy".to_string(),
+            "1".to_string(),
+            "src/text_files/mary.txt".to_string(),
+            "lanb".to_string()
+        ]);
+        let result = run(input_args, &mut RunContext::new()).unwrap_or_default();
+        assert_eq!(result, 3);
+    }
+
+    #[test]
+    fn multiline_test() {
+        let count = |query: &str| {
+            let input_args = Some(vec![
+                "-cUP".to_string(),
+                "src/text_files/mary.txt".to_string(),
+                query.to_string()
+            ]);
+            run(input_args, &mut RunContext::new()).unwrap_or_default()
+        };
+        assert_eq!(count(r"lamb,\nIts"), 2);
+        assert_eq!(count(r"school one day,\s+Which.*\n.*children"), 3);
+        assert_eq!(count(r"^It \w+"), 2);
+
+        let matches = |query: &str| {
+            let input_args = Some(vec![
+                "-UP".to_string(),
+                "--count-matches".to_string(),
+                "src/text_files/mary.txt".to_string(),
+                query.to_string()
+            ]);
+            run(input_args, &mut RunContext::new()).unwrap_or_default()
+        };
+        assert_eq!(matches(r"lamb,\nIts"), 1);
+        assert_eq!(matches(r"school one day,\s+Which.*\n.*children"), 1);
+        assert_eq!(matches(r"lamb"), count(r"lamb"));
+    }
+
+    #[test]
+    fn boolean_query_test() {
+        let count = |query: &str, file_scope: bool| {
+            let mut args = vec![
+                "-c".to_string(),
+                "--boolean".to_string(),
+                "src/text_files/mary.txt".to_string(),
+                query.to_string()
+            ];
+            if file_scope {
+                args.push("--file-scope".to_string());
+            }
+            run(Some(args), &mut RunContext::new()).unwrap_or_default()
+        };
+        assert_eq!(count("lamb AND NOT school", false), 2);
+        assert_eq!(count("Mary NEAR/1 lamb", false), 3);
+        assert_eq!(count("(lamb OR snow) NOT goose", true), 4);
+        assert_eq!(count("lamb AND NOT school", true), 0);
+
+        // A term only in a comment doesn't count towards the query
+        let root = test_dir("boolean_region_test", &[
+            ("lamb.py", "x = lamb  # school\ny = lamb + school\nz = 'lamb'\n")
+        ]);
+        let path = root.join("lamb.py");
+        let code = count_in(&path, &["-c", "--boolean", "--code-only"], "lamb AND school");
+        let not_code = count_in(&path, &["-c", "--boolean", "--code-only"], "lamb AND NOT school");
+        let _ = fs::remove_dir_all(&root);
+        assert_eq!(code.ok(), Some(1));
+        assert_eq!(not_code.ok(), Some(1));
+    }
+
+    #[test]
+    fn throw_pattern_error() {
+        let args = Some(vec![
+            "--regex".to_string(),
+            "src/text_files/mary.txt".to_string(),
+            "(mary".to_string(),
+        ]);
+        let result = run(args, &mut RunContext::new());
+        assert!(result.is_err());
+        if let Err(err) = result {
+            assert!(matches!(err.error_type, PscanError::Pattern));
+        }
+    }
+
     #[test]
     fn throw_argument_error() {
         let args = Some(vec![
@@ -575,7 +1822,7 @@ mod tests {
             "mary".to_string(),
         ]);
     
-        let result = run(args);
+        let result = run(args, &mut RunContext::new());
         assert!(result.is_err());  // Should fail when no -r on directory
    
         if let Err(err) = result {
@@ -590,7 +1837,7 @@ mod tests {
             "mary".to_string(),
         ]);
     
-        let result = run(args);
+        let result = run(args, &mut RunContext::new());
         assert!(result.is_err());  // Should fail when no -r on directory
    
         // Optional: check error type
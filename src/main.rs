@@ -1,9 +1,8 @@
-use pscan;
 use std::process;
 
 
 fn main() {
-    match pscan::run(None) {
+    match pscan::run(None, &mut pscan::RunContext::new()) {
         Ok(exit_code) => exit_code,
         Err(response) => {
             process::exit(pscan::error_handler(response))
@@ -0,0 +1,217 @@
+// An Aho-Corasick automaton for searching many literal patterns in a single
+// pass over the line. Patterns are stored case folded, so the automaton
+// finds case-insensitive candidates and case-sensitive patterns are then
+// checked against the original text.
+//
+// Transitions for ASCII characters are filled in for every state once the
+// failure links are known, so the common case is one table lookup per
+// character. Other characters follow failure links at search time.
+
+use std::collections::VecDeque;
+
+use crate::matcher::{casefold, Span};
+
+
+const ASCII: usize = 128;
+const ROOT: usize = 0;
+
+
+struct State {
+    ascii: [usize; ASCII],
+    other: Vec<(char, usize)>,
+    fail: usize,
+    outputs: Vec<usize>  // patterns ending here, including through `fail`
+}
+
+impl State {
+    fn new() -> Self {
+        State { 
+            ascii: [ROOT; ASCII], 
+            other: Vec::new(), 
+            fail: ROOT, 
+            outputs: Vec::new() 
+        }
+    }
+
+    fn child(&self, c: char) -> Option<usize> {
+        if (c as usize) < ASCII {
+            Some(self.ascii[c as usize]).filter(|s| *s != ROOT)
+        } else {
+            self.other.iter().find(|(k, _)| *k == c).map(|(_, s)| *s)
+        }
+    }
+}
+
+pub struct AhoCorasick {
+    states: Vec<State>,
+    lengths: Vec<usize>,          // in characters
+    exact: Vec<Option<String>>    // set for case-sensitive patterns
+}
+
+impl AhoCorasick {
+
+    // Each pattern is given as its case folded characters, plus the original
+    // text if it has to match case-sensitively
+    pub fn new(patterns: &[(Vec<char>, Option<String>)]) -> Self {
+
+        let mut states: Vec<State> = vec![State::new()];
+
+        for (idx, (chars, _)) in patterns.iter().enumerate() {
+            let mut current = ROOT;
+            for &c in chars {
+                current = match states[current].child(c) {
+                    Some(next) => next,
+                    None => {
+                        states.push(State::new());
+                        let next = states.len() - 1;
+                        if (c as usize) < ASCII {
+                            states[current].ascii[c as usize] = next;
+                        } else {
+                            states[current].other.push((c, next));
+                        }
+                        next
+                    }
+                };
+            }
+            states[current].outputs.push(idx);
+        }
+
+        // Breadth first, so a state's failure link always points at a state
+        // that has already been completed
+        let mut queue: VecDeque<usize> = VecDeque::new();
+        queue.push_back(ROOT);
+
+        while let Some(current) = queue.pop_front() {
+
+            let fail = states[current].fail;
+
+            for c in 0..ASCII {
+                let child = states[current].ascii[c];
+                if child != ROOT {
+                    states[child].fail = match current {
+                        ROOT => ROOT,
+                        _ => states[fail].ascii[c]
+                    };
+                    queue.push_back(child);
+                } else if current != ROOT {
+                    states[current].ascii[c] = states[fail].ascii[c];
+                }
+            }
+
+            let others = states[current].other.clone();
+            for (c, child) in others {
+                states[child].fail = match current {
+                    ROOT => ROOT,
+                    _ => Self::next_in(&states, fail, c)
+                };
+                queue.push_back(child);
+            }
+
+            if current != ROOT {
+                let inherited = states[fail].outputs.clone();
+                states[current].outputs.extend(inherited);
+            }
+        }
+
+        AhoCorasick {
+            states,
+            lengths: patterns.iter().map(|(chars, _)| chars.len()).collect(),
+            exact: patterns.iter().map(|(_, exact)| exact.clone()).collect()
+        }
+    }
+
+    fn next_in(states: &[State], mut current: usize, c: char) -> usize {
+        if (c as usize) < ASCII {
+            return states[current].ascii[c as usize]
+        }
+        loop {
+            if let Some(next) = states[current].child(c) {
+                return next
+            }
+            if current == ROOT {
+                return ROOT
+            }
+            current = states[current].fail;
+        }
+    }
+
+    // Every occurrence of every pattern, overlapping ones included, ordered
+    // by where they end
+    pub fn find_overlapping(&self, haystack: &str) -> Vec<Span> {
+
+        let mut spans: Vec<Span> = Vec::new();
+        let mut char_starts: Vec<usize> = Vec::new();
+        let mut current = ROOT;
+
+        for (offset, c) in haystack.char_indices() {
+            char_starts.push(offset);
+            current = Self::next_in(&self.states, current, casefold::fold(c));
+
+            for &pattern in &self.states[current].outputs {
+                let len = self.lengths[pattern];
+                let start = char_starts[char_starts.len() - len];
+                let end = offset + c.len_utf8();
+
+                if let Some(exact) = &self.exact[pattern]
+                    && haystack[start..end] != *exact {
+                    continue
+                }
+                spans.push(Span { start, end, pattern, distance: 0 });
+            }
+        }
+        spans
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn pattern(text: &str, case_sensitive: bool) -> (Vec<char>, Option<String>) {
+        let folded = text.chars().map(casefold::fold).collect();
+        (folded, Some(text.to_string()).filter(|_| case_sensitive))
+    }
+
+    fn found(ac: &AhoCorasick, haystack: &str) -> Vec<(usize, usize, usize)> {
+        ac.find_overlapping(haystack)
+            .iter()
+            .map(|s| (s.start, s.end, s.pattern))
+            .collect()
+    }
+
+    #[test]
+    fn overlapping_matches() {
+        let ac = AhoCorasick::new(&[
+            pattern("he", true),
+            pattern("she", true),
+            pattern("hers", true),
+            pattern("his", true)
+        ]);
+        assert_eq!(found(&ac, "ushers"), vec![(1, 4, 1), (2, 4, 0), (2, 6, 2)]);
+    }
+
+    #[test]
+    fn mixed_case_sensitivity() {
+        let ac = AhoCorasick::new(&[
+            pattern("Print", true),
+            pattern("debug", false)
+        ]);
+        let haystack = "print(DEBUG) Print(Debug)";
+        let expected = vec![(6, 11, 1), (13, 18, 0), (19, 24, 1)];
+        assert_eq!(found(&ac, haystack), expected);
+    }
+
+    #[test]
+    fn non_ascii_patterns() {
+        let ac = AhoCorasick::new(&[
+            pattern("οδοσ", false),
+            pattern("δοσ", false),
+            pattern("straße", false)
+        ]);
+        let haystack = "ΟΔΟΣ STRAẞE";
+        let expected = vec![(0, 8, 0), (2, 8, 1), (9, 17, 2)];
+        assert_eq!(found(&ac, haystack), expected);
+    }
+}
@@ -0,0 +1,164 @@
+// Unicode simple case folding. Every character folds to exactly one other
+// character, so a folded line has the same number of characters as the
+// original and match positions can be mapped back to the original bytes.
+// This is what keeps spans correct for text like "İ", "ß" or "Σ", where
+// `to_lowercase` changes the byte length of the line.
+
+
+// Simple folds that `char::to_lowercase` doesn't give us, either because
+// the lowercase form is a different letter variant (final sigma, long s)
+// or because the character is already lowercase.
+const SPECIAL_FOLDS: &[(char, char)] = &[
+    ('\u{00B5}', '\u{03BC}'),  // µ micro sign
+    ('\u{017F}', 's'),         // ſ long s
+    ('\u{0345}', '\u{03B9}'),  // combining ypogegrammeni
+    ('\u{03C2}', '\u{03C3}'),  // ς final sigma
+    ('\u{03D0}', '\u{03B2}'),  // ϐ
+    ('\u{03D1}', '\u{03B8}'),  // ϑ
+    ('\u{03D5}', '\u{03C6}'),  // ϕ
+    ('\u{03D6}', '\u{03C0}'),  // ϖ
+    ('\u{03F0}', '\u{03BA}'),  // ϰ
+    ('\u{03F1}', '\u{03C1}'),  // ϱ
+    ('\u{03F5}', '\u{03B5}'),  // ϵ
+    ('\u{1C80}', '\u{0432}'),  // old Cyrillic variants
+    ('\u{1C81}', '\u{0434}'),
+    ('\u{1C82}', '\u{043E}'),
+    ('\u{1C83}', '\u{0441}'),
+    ('\u{1C84}', '\u{0442}'),
+    ('\u{1C85}', '\u{0442}'),
+    ('\u{1C86}', '\u{044A}'),
+    ('\u{1C87}', '\u{0463}'),
+    ('\u{1C88}', '\u{A64B}'),
+    ('\u{1E9B}', '\u{1E61}'),  // ẛ
+    ('\u{1FBE}', '\u{03B9}'),  // prosgegrammeni
+];
+
+// Characters that fold onto a letter but can't be reached from it through
+// `char::to_uppercase`, needed when expanding a character into every form
+// that folds the same way.
+const EXTRA_FORMS: &[char] = &[
+    '\u{01C5}', '\u{01C8}', '\u{01CB}', '\u{01F2}',  // ǅ ǈ ǋ ǲ titlecase
+    '\u{03F4}',  // ϴ
+    '\u{1E9E}',  // ẞ capital sharp s
+    '\u{2126}',  // Ω ohm sign
+    '\u{212A}',  // K kelvin sign
+    '\u{212B}',  // Å angstrom sign
+];
+
+
+pub fn fold(c: char) -> char {
+    if c.is_ascii() {
+        return c.to_ascii_lowercase()
+    }
+    if let Some((_, folded)) = SPECIAL_FOLDS.iter().find(|(from, _)| *from == c) {
+        return *folded
+    }
+    // Characters such as 'İ' lowercase to several characters and have no
+    // simple fold, so they only match themselves.
+    let mut lower = c.to_lowercase();
+    match (lower.next(), lower.next()) {
+        (Some(l), None) => l,
+        _ => c
+    }
+}
+
+// Every character that folds to the same thing as `c`, including `c`
+pub fn variants(c: char) -> Vec<char> {
+    let folded = fold(c);
+    let mut forms: Vec<char> = vec![c];
+
+    let mut candidates: Vec<char> = vec![folded];
+    candidates.extend(folded.to_uppercase());
+    candidates.extend(SPECIAL_FOLDS.iter().map(|(from, _)| *from));
+    candidates.extend(EXTRA_FORMS);
+
+    for candidate in candidates {
+        if fold(candidate) == folded && !forms.contains(&candidate) {
+            forms.push(candidate);
+        }
+    }
+    forms
+}
+
+// Byte spans of every non-overlapping, case-insensitive occurrence of
+// `needle` (already folded) in `haystack`, as offsets into the original
+// unfolded haystack. Occurrences rejected by `accept` are skipped and the 
+// search carries on from the next character.
+pub fn find_iter(
+    haystack: &str, 
+    needle: &[char],
+    accept: impl Fn(usize, usize) -> bool
+) -> Vec<(usize, usize)> {
+
+    let mut spans: Vec<(usize, usize)> = Vec::new();
+    if needle.is_empty() {
+        return spans
+    }
+
+    let folded: Vec<(usize, char)> = haystack
+        .char_indices()
+        .map(|(i, c)| (i, fold(c)))
+        .collect();
+
+    let mut i: usize = 0;
+    while i + needle.len() <= folded.len() {
+        let is_match = folded[i..i + needle.len()]
+            .iter()
+            .zip(needle)
+            .all(|((_, h), n)| h == n);
+
+        let end = match folded.get(i + needle.len()) {
+            Some((offset, _)) => *offset,
+            None => haystack.len()
+        };
+
+        if is_match && accept(folded[i].0, end) {
+            spans.push((folded[i].0, end));
+            i += needle.len();
+        } else {
+            i += 1;
+        }
+    }
+    spans
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn folded(s: &str) -> Vec<char> {
+        s.chars().map(fold).collect()
+    }
+
+    #[test]
+    fn sigma_forms_fold_together() {
+        assert_eq!(fold('Σ'), 'σ');
+        assert_eq!(fold('ς'), 'σ');
+        let line = "ΟΔΟΣ οδος";
+        let spans = find_iter(line, &folded("οδοσ"), |_, _| true);
+        assert_eq!(spans, vec![(0, 8), (9, 17)]);
+    }
+
+    #[test]
+    fn spans_use_original_byte_offsets() {
+        // 'İ' has no simple fold, and lowercases to two characters
+        let line = "İstanbul ISTANBUL";
+        let spans = find_iter(line, &folded("istanbul"), |_, _| true);
+        assert_eq!(spans, vec![(10, 18)]);
+        assert_eq!(&line[10..18], "ISTANBUL");
+
+        let line = "STRASSE straße";
+        let spans = find_iter(line, &folded("STRAẞE"), |_, _| true);
+        assert_eq!(spans, vec![(8, 15)]);
+    }
+
+    #[test]
+    fn variants_cover_compatibility_characters() {
+        let forms = variants('k');
+        assert!(forms.contains(&'K'));
+        assert!(forms.contains(&'\u{212A}'));
+        assert!(variants('σ').contains(&'ς'));
+    }
+}
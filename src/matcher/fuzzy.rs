@@ -0,0 +1,274 @@
+// Approximate matching for --fuzzy: finds substrings within a Levenshtein
+// distance of the pattern, using Myers' bit-parallel algorithm. Each bit of
+// a machine word stands for one character of the pattern, so a whole column
+// of the edit distance table is updated per character of text. That limits
+// patterns to 64 characters.
+//
+// A forward pass gives the best distance of a match ending at each
+// position. Where a match starts is found by running the pattern backwards
+// from its end.
+
+use std::collections::HashMap;
+
+use crate::matcher::casefold;
+
+
+const MAX_LEN: usize = 64;
+
+
+// Bit masks of where each character occurs in the pattern
+struct Masks {
+    ascii: Vec<u64>,
+    other: HashMap<char, u64>
+}
+
+impl Masks {
+
+    fn new(chars: impl Iterator<Item = char>) -> Self {
+        let mut masks = Masks { ascii: vec![0; 128], other: HashMap::new() };
+        for (i, c) in chars.enumerate() {
+            if (c as usize) < 128 {
+                masks.ascii[c as usize] |= 1 << i;
+            } else {
+                *masks.other.entry(c).or_insert(0) |= 1 << i;
+            }
+        }
+        masks
+    }
+
+    fn get(&self, c: char) -> u64 {
+        match (c as usize) < 128 {
+            true => self.ascii[c as usize],
+            false => self.other.get(&c).copied().unwrap_or(0)
+        }
+    }
+}
+
+// One column of the edit distance table, stored as the vertical deltas
+struct Column {
+    pv: u64,
+    mv: u64,
+    score: usize,
+    last: u64
+}
+
+impl Column {
+
+    fn new(len: usize) -> Self {
+        Column { pv: !0, mv: 0, score: len, last: 1 << (len - 1) }
+    }
+
+    // Moves on by one text character. `anchored` pins the match to start
+    // at the first character, rather than anywhere in the text.
+    fn step(&mut self, eq: u64, anchored: bool) -> usize {
+        let xv = eq | self.mv;
+        let xh = ((eq & self.pv).wrapping_add(self.pv) ^ self.pv) | eq;
+        let mut ph = self.mv | !(xh | self.pv);
+        let mut mh = self.pv & xh;
+
+        if ph & self.last != 0 {
+            self.score += 1;
+        } else if mh & self.last != 0 {
+            self.score -= 1;
+        }
+
+        ph = (ph << 1) | u64::from(anchored);
+        mh <<= 1;
+        self.pv = mh | !(xv | ph);
+        self.mv = ph & xv;
+        self.score
+    }
+}
+
+
+pub struct Fuzzy {
+    len: usize,
+    max_distance: usize,
+    case_insensitive: bool,
+    forward: Masks,
+    backward: Masks
+}
+
+impl Fuzzy {
+
+    pub fn new(
+        pattern: &str,
+        max_distance: usize,
+        case_insensitive: bool
+    ) -> Result<Self, String> {
+
+        let chars: Vec<char> = match case_insensitive {
+            true => pattern.chars().map(casefold::fold).collect(),
+            false => pattern.chars().collect()
+        };
+        if chars.len() > MAX_LEN {
+            return Err(format!("too long for --fuzzy, the limit is {} characters", MAX_LEN))
+        }
+        if max_distance >= chars.len() {
+            return Err(
+                format!("too short for --fuzzy {}, anything would match", max_distance)
+            )
+        }
+        Ok(Fuzzy {
+            len: chars.len(),
+            max_distance,
+            case_insensitive,
+            forward: Masks::new(chars.iter().copied()),
+            backward: Masks::new(chars.iter().rev().copied())
+        })
+    }
+
+    // Non-overlapping matches as (start, end, distance), the closest ones
+    // chosen first. Where `accept` rejects a match, starts further back
+    // with the same distance are tried before it's skipped.
+    pub fn find_iter(
+        &self,
+        text: &str,
+        accept: impl Fn(usize, usize) -> bool
+    ) -> Vec<(usize, usize, usize)> {
+
+        let chars: Vec<(usize, char)> = text
+            .char_indices()
+            .map(|(i, c)| match self.case_insensitive {
+                true => (i, casefold::fold(c)),
+                false => (i, c)
+            })
+            .collect();
+        let offset = |idx: usize| chars.get(idx).map_or(text.len(), |(i, _)| *i);
+
+        // (distance, end) of every position a close enough match ends at,
+        // in characters
+        let mut column = Column::new(self.len);
+        let mut ends: Vec<(usize, usize)> = Vec::new();
+        for (idx, (_, c)) in chars.iter().enumerate() {
+            let score = column.step(self.forward.get(*c), false);
+            if score <= self.max_distance {
+                ends.push((score, idx + 1));
+            }
+        }
+        ends.sort();
+
+        // Kept sorted by start. Matches don't overlap, so the only one a
+        // new match can run into is the last to start before it ends.
+        let mut found: Vec<(usize, usize, usize)> = Vec::new();
+        for (distance, end) in ends {
+
+            let end_byte = offset(end);
+            let next = found.partition_point(|(s, _, _)| *s < end_byte);
+            let free_from = match next {
+                0 => 0,
+                n => found[n - 1].1
+            };
+            if free_from >= end_byte {
+                continue
+            }
+
+            // The closest start that still gives the same distance. A match
+            // is at most `distance` characters longer than the pattern.
+            let mut column = Column::new(self.len);
+            for start in (end.saturating_sub(self.len + distance)..end).rev() {
+                let start_byte = offset(start);
+                if start_byte < free_from {
+                    break
+                }
+                if column.step(self.backward.get(chars[start].1), true) <= distance
+                    && accept(start_byte, end_byte) {
+                    found.insert(next, (start_byte, end_byte, distance));
+                    break
+                }
+            }
+        }
+        found
+    }
+}
+
+// Plain Levenshtein distance, to check the bit-parallel version against
+#[cfg(test)]
+fn levenshtein(a: &[char], b: &[char]) -> usize {
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.iter().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let next = (diagonal + usize::from(ca != cb))
+                .min(row[j] + 1)
+                .min(row[j + 1] + 1);
+            diagonal = row[j + 1];
+            row[j + 1] = next;
+        }
+    }
+    row[b.len()]
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn matches<'a>(pattern: &str, k: usize, text: &'a str) -> Vec<(&'a str, usize)> {
+        let fuzzy = match Fuzzy::new(pattern, k, false) {
+            Ok(f) => f,
+            Err(msg) => panic!("{}", msg)
+        };
+        fuzzy.find_iter(text, |_, _| true)
+            .into_iter()
+            .map(|(s, e, d)| (&text[s..e], d))
+            .collect()
+    }
+
+    #[test]
+    fn finds_closest_substrings() {
+        assert_eq!(
+            matches("receive_packet", 2, "if recieve_packet(buf) {"),
+            vec![("recieve_packet", 2)]
+        );
+        assert_eq!(
+            matches("colour", 1, "color, colours and a collar"),
+            vec![("color", 1), ("colour", 0)]
+        );
+        assert_eq!(matches("packet", 1, "pocket backs"), vec![("pocket", 1)]);
+        assert!(matches("receive", 1, "recipe").is_empty());
+        assert_eq!(matches("colour", 1, &"colour ".repeat(10_000)).len(), 10_000);
+    }
+
+    #[test]
+    fn rejected_starts_are_retried() {
+        let fuzzy = match Fuzzy::new("colour", 1, false) {
+            Ok(f) => f,
+            Err(msg) => panic!("{}", msg)
+        };
+        // "olour" is as close, but doesn't start a word
+        let text = "the xolour";
+        let at_word = |s: usize, _: usize| s == 0 || text[..s].ends_with(' ');
+        assert_eq!(fuzzy.find_iter(text, at_word), vec![(4, 10, 1)]);
+    }
+
+    #[test]
+    fn distances_agree_with_levenshtein() {
+        let pattern = "transmute";
+        let text = "let x = unsafe { std::mem::transmte::<u32, f32>(y) }; tranmsute";
+        let chars: Vec<char> = pattern.chars().collect();
+        let fuzzy = match Fuzzy::new(pattern, 2, false) {
+            Ok(f) => f,
+            Err(msg) => panic!("{}", msg)
+        };
+        let found = fuzzy.find_iter(text, |_, _| true);
+        assert_eq!(found.len(), 2);
+        for (s, e, d) in found {
+            let found: Vec<char> = text[s..e].chars().collect();
+            assert_eq!(levenshtein(&chars, &found), d);
+        }
+    }
+
+    #[test]
+    fn case_insensitive_and_limits() {
+        let fuzzy = match Fuzzy::new("ΟΔΟΣ", 1, true) {
+            Ok(f) => f,
+            Err(msg) => panic!("{}", msg)
+        };
+        assert_eq!(fuzzy.find_iter("οδός", |_, _| true), vec![(0, 8, 1)]);
+        assert!(Fuzzy::new("ab", 2, false).is_err());
+        assert!(Fuzzy::new(&"a".repeat(65), 1, false).is_err());
+    }
+}
@@ -0,0 +1,213 @@
+// Shell-style wildcards, translated into the regex syntax so they run on
+// the same engine:
+//
+//     *        any run of characters on the line, as few as possible.
+//              One at either end of the glob is dropped.
+//     ?        any single character
+//     [abc]    one of the characters, with ranges ([a-z]) and negation
+//              ([!abc] or [^abc])
+//     \*       a literal '*', likewise for the other special characters
+//
+// Path globs, for matching file paths, are the same except that `*` and
+// `?` stop at a '/', and `**` crosses directories: `**/` is any number of
+// them, and a trailing `/**` everything inside.
+
+
+// Everything else that is special in a regex and has to be escaped
+const REGEX_SPECIAL: &[char] = &[
+    '.', '+', '(', ')', '|', '{', '}', '^', '$', '\\', '[', ']', '*', '?'
+];
+
+
+pub fn to_regex(glob: &str) -> String {
+
+    // A glob matches anywhere in the line, so a `*` at either end only
+    // stretches what is highlighted into the rest of the line
+    let mut chars: Vec<char> = glob.chars().collect();
+    let leading = chars.iter().take_while(|c| **c == '*').count();
+    chars.drain(..leading);
+    while chars.last() == Some(&'*') && !is_escaped(&chars, chars.len() - 1) {
+        chars.pop();
+    }
+    if chars.is_empty() && !glob.is_empty() {
+        return String::from(".*")
+    }
+
+    let mut regex = String::new();
+    let mut i: usize = 0;
+
+    while i < chars.len() {
+        match chars[i] {
+            '*' => regex.push_str(".*?"),
+            '?' => regex.push('.'),
+            '[' if let Some(end) = class_end(&chars, i) => {
+                push_class(&mut regex, &chars[i + 1..end]);
+                i = end;
+            },
+            '\\' if i + 1 < chars.len() => {
+                i += 1;
+                push_literal(&mut regex, chars[i]);
+            },
+            c => push_literal(&mut regex, c)
+        }
+        i += 1;
+    }
+    regex
+}
+
+// A regex matching a whole path against the glob
+pub fn to_path_regex(glob: &str) -> String {
+
+    let chars: Vec<char> = glob.chars().collect();
+    let mut regex = String::from("^(?:");
+    let mut i: usize = 0;
+
+    while i < chars.len() {
+        let dir_start = i == 0 || chars[i - 1] == '/';
+        match chars[i] {
+            '*' if chars.get(i + 1) == Some(&'*') && dir_start => {
+                match chars.get(i + 2) {
+                    Some('/') => {
+                        regex.push_str("(?:.*/)?");
+                        i += 2;
+                    },
+                    None => {
+                        regex.push_str(".*");
+                        i += 1;
+                    },
+                    // Anywhere else ** is just *
+                    Some(_) => {
+                        regex.push_str("[^/]*");
+                        i += 1;
+                    }
+                }
+            },
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            '[' if let Some(end) = class_end(&chars, i) => {
+                push_class(&mut regex, &chars[i + 1..end]);
+                i = end;
+            },
+            '\\' if i + 1 < chars.len() => {
+                i += 1;
+                push_literal(&mut regex, chars[i]);
+            },
+            c => push_literal(&mut regex, c)
+        }
+        i += 1;
+    }
+    regex.push_str(")$");
+    regex
+}
+
+// A class's contents, between the brackets
+fn push_class(regex: &mut String, class: &[char]) {
+    regex.push('[');
+    let class = match class.first() {
+        Some('!' | '^') => {
+            regex.push('^');
+            &class[1..]
+        },
+        _ => class
+    };
+    for &c in class {
+        if matches!(c, '\\' | '[' | ']') {
+            regex.push('\\');
+        }
+        regex.push(c);
+    }
+    regex.push(']');
+}
+
+// Whether the character at `idx` follows an odd number of backslashes
+fn is_escaped(chars: &[char], idx: usize) -> bool {
+    chars[..idx].iter().rev().take_while(|c| **c == '\\').count() % 2 == 1
+}
+
+fn push_literal(regex: &mut String, c: char) {
+    if REGEX_SPECIAL.contains(&c) {
+        regex.push('\\');
+    }
+    regex.push(c);
+}
+
+// Index of the ']' closing the class opened at `start`. A ']' straight
+// after the '[' (or after the negation) is part of the class. Without a
+// closing ']' the '[' is just a character.
+fn class_end(chars: &[char], start: usize) -> Option<usize> {
+    let mut i = start + 1;
+    if matches!(chars.get(i), Some('!') | Some('^')) {
+        i += 1;
+    }
+    if chars.get(i) == Some(&']') {
+        i += 1;
+    }
+    chars[i.min(chars.len())..]
+        .iter()
+        .position(|c| *c == ']')
+        .map(|p| i + p)
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::matcher::Regex;
+
+    fn found(glob: &str, line: &str) -> Vec<String> {
+        let re = match Regex::new(&to_regex(glob), false) {
+            Ok(r) => r,
+            Err(msg) => panic!("{} gave a bad regex: {}", glob, msg)
+        };
+        re.find_iter(line)
+            .into_iter()
+            .map(|(s, e)| line[s..e].to_string())
+            .collect()
+    }
+
+    #[test]
+    fn wildcards() {
+        assert_eq!(
+            found("print(*debug*)", "print(debug) x print(\"debug:\", y) z"),
+            vec!["print(debug)", "print(\"debug:\", y)"]
+        );
+        assert_eq!(found("v?.?", "v1.2 and v10.3"), vec!["v1.2"]);
+        assert_eq!(found(r"2\*3", "2*3 223"), vec!["2*3"]);
+    }
+
+    #[test]
+    fn stars_at_the_ends() {
+        assert_eq!(found("print*", "    print(x)  # done"), vec!["print"]);
+        assert_eq!(found("*lamb", "Mary had a little lamb"), vec!["lamb"]);
+        assert_eq!(found("**a*b**", "xx a yy b zz"), vec!["a yy b"]);
+        assert_eq!(found(r"2\*", "2*3 22"), vec!["2*"]);
+        assert_eq!(found(r"2\\*", r"2\3"), vec!["2\\"]);
+        assert_eq!(found("*", "abc"), vec!["abc"]);
+    }
+
+    #[test]
+    fn path_globs() {
+        let matches = |glob: &str, path: &str| match Regex::new(&to_path_regex(glob), false) {
+            Ok(re) => re.is_match(path),
+            Err(msg) => panic!("{} gave a bad regex: {}", glob, msg)
+        };
+        assert!(matches("src/**/*.py", "src/a/b/c.py"));
+        assert!(matches("src/**/*.py", "src/c.py"));
+        assert!(!matches("src/*.py", "src/a/c.py"));
+        assert!(matches("**/migrations/**", "app/migrations/0001.py"));
+        assert!(matches("**/migrations/**", "migrations/x"));
+        assert!(!matches("**/migrations/**", "app/migrations"));
+        assert!(!matches("*.py", "a.pyc"));
+        assert!(matches("a?c/[!x]", "abc/y"));
+    }
+
+    #[test]
+    fn character_classes() {
+        assert_eq!(found("[bc]at", "bat cat hat"), vec!["bat", "cat"]);
+        assert_eq!(found("[!bc]at", "bat cat hat"), vec!["hat"]);
+        assert_eq!(found("x[]]", "x] x["), vec!["x]"]);
+        assert_eq!(found("a[b", "a[b"), vec!["a[b"]);
+        assert_eq!(found("[a-c]1", "a1 d1 c1"), vec!["a1", "c1"]);
+    }
+}
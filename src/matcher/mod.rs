@@ -0,0 +1,535 @@
+pub mod aho_corasick;
+pub mod casefold;
+pub mod fuzzy;
+pub mod glob;
+pub mod query;
+pub mod regex;
+pub use aho_corasick::AhoCorasick;
+pub use fuzzy::Fuzzy;
+pub use query::BooleanQuery;
+pub use regex::Regex;
+
+
+use crate::arguments::parser::ParsedArgs;
+use crate::replace;
+use regex::is_word_char;
+
+
+// Where a match has to start and end: anywhere, on word boundaries (-w)
+// or covering a whole line (-x)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Boundary {
+    None,
+    Word,
+    Line
+}
+
+impl Boundary {
+
+    fn accepts(&self, line: &str, start: usize, end: usize) -> bool {
+        match self {
+            Self::None => true,
+            Self::Word => {
+                let before = line[..start].chars().next_back();
+                let after = line[end..].chars().next();
+                !before.is_some_and(is_word_char) 
+                    && !after.is_some_and(is_word_char)
+            },
+            // Lines within the text, for --multiline
+            Self::Line => {
+                (start == 0 || line[..start].ends_with('\n'))
+                    && (end == line.len() || line[end..].starts_with('\n'))
+            }
+        }
+    }
+}
+
+
+// A match in a line. `pattern` is the index of the pattern that matched,
+// in the order the patterns were given, and `distance` the number of edits
+// a --fuzzy match is away from it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub pattern: usize,
+    pub distance: usize
+}
+
+
+pub enum Pattern {
+    Literal(String),
+    FoldedLiteral(Vec<char>),  // the query, case folded
+    Regex(Regex, usize),  // and the group to report, 0 for the whole match
+    Fuzzy(Fuzzy)
+}
+
+pub struct Matcher {
+    patterns: Vec<Pattern>,
+    automaton: Option<AhoCorasick>,  // built when every pattern is a literal
+    boundary: Boundary,
+    query: Option<BooleanQuery>      // with --boolean, the patterns are its terms
+}
+
+impl Matcher {
+
+    pub fn new(
+        queries: &[String], 
+        parsed_args: &ParsedArgs
+    ) -> Result<Self, String> {
+
+        let boundary = if parsed_args.line_regexp {
+            Boundary::Line
+        } else if parsed_args.word_regexp {
+            Boundary::Word
+        } else {
+            Boundary::None
+        };
+
+        let query = match parsed_args.boolean {
+            true => match queries {
+                [query] => match BooleanQuery::parse(query) {
+                    Ok(q) => Some(q),
+                    Err(msg) => {
+                        return Err(format!("Invalid query '{}': {}", query, msg))
+                    }
+                },
+                _ => return Err(String::from("--boolean takes a single QUERY"))
+            },
+            false => None
+        };
+        let queries = match &query {
+            Some(q) => &q.terms,
+            None => queries
+        };
+
+        // Wildcard queries are translated into regexes
+        let is_regex = parsed_args.regex || parsed_args.glob_query;
+
+        let mut patterns: Vec<Pattern> = Vec::new();
+
+        for query in queries {
+
+            if query.is_empty() {
+                return Err(String::from("Patterns can't be empty"))
+            }
+
+            let translated: String;
+            let query = match parsed_args.glob_query {
+                true => {
+                    translated = glob::to_regex(query);
+                    &translated
+                },
+                false => query
+            };
+
+            let case_sensitive = match parsed_args.smart_case {
+                true => has_uppercase(query, is_regex),
+                false => parsed_args.case_sensitive
+            };
+
+            // A regex without any special characters is searched for as a
+            // literal, which is faster and lets it join the automaton. Not
+            // with --capture though, which needs the regex's groups.
+            let literal = match is_regex {
+                true if parsed_args.capture.is_some() => None,
+                true => regex_literal(query),
+                false => Some(query.to_string())
+            };
+
+            let pattern = match literal {
+                Some(text) if let Some(edits) = parsed_args.fuzzy => {
+                    match Fuzzy::new(&text, edits, !case_sensitive) {
+                        Ok(f) => Pattern::Fuzzy(f),
+                        Err(msg) => {
+                            return Err(
+                                format!("Invalid pattern '{}': {}", query, msg)
+                            )
+                        }
+                    }
+                },
+                Some(text) if case_sensitive => Pattern::Literal(text),
+                Some(text) => Pattern::FoldedLiteral(fold_str(&text)),
+                None => {
+                    let re = Regex::with_boundary(
+                        query, !case_sensitive, boundary
+                    );
+                    let re = match re {
+                        Ok(re) => re,
+                        Err(msg) => {
+                            return Err(
+                                format!("Invalid pattern '{}': {}", query, msg)
+                            )
+                        }
+                    };
+                    let group = match parsed_args.capture {
+                        Some(group) => match re.group_index(group) {
+                            Some(idx) => idx,
+                            None => {
+                                return Err(format!(
+                                    "Pattern '{}' has no capture group '{}'", 
+                                    query, 
+                                    group
+                                ))
+                            }
+                        },
+                        None => 0
+                    };
+                    Pattern::Regex(re, group)
+                }
+            };
+            patterns.push(pattern);
+        }
+
+        let all_literals = patterns
+            .iter()
+            .all(|p| matches!(p, Pattern::Literal(_) | Pattern::FoldedLiteral(_)));
+        let automaton = match all_literals && patterns.len() > 1 {
+            true => Some(Self::build_automaton(&patterns)),
+            false => None
+        };
+
+        Ok(Matcher { patterns, automaton, boundary, query })
+    }
+
+    fn build_automaton(patterns: &[Pattern]) -> AhoCorasick {
+        let keys: Vec<(Vec<char>, Option<String>)> = patterns
+            .iter()
+            .map(|p| match p {
+                Pattern::Literal(text) => (fold_str(text), Some(text.clone())),
+                Pattern::FoldedLiteral(chars) => (chars.clone(), None),
+                _ => (Vec::new(), None)
+            })
+            .collect();
+        AhoCorasick::new(&keys)
+    }
+
+    // Whether lines have to be looked at together rather than one at a time
+    pub fn is_boolean(&self) -> bool {
+        self.query.is_some()
+    }
+
+    // Every non-overlapping match in the line, from any of the patterns. 
+    // Where matches from different patterns overlap, the one starting first
+    // wins, then the longest.
+    pub fn find_iter(&self, line: &str) -> Vec<Span> {
+        self.resolve_overlaps(self.find_candidates(line))
+    }
+
+    // Matches for each of the lines and whether the line counts as a match.
+    // With a boolean query that's decided by the query, over single lines
+    // or the whole file, and terms that only appear under a NOT aren't 
+    // highlighted. Matches `keep` turns down, given the line's index, are
+    // dropped before anything is decided.
+    pub fn find_lines(
+        &self, 
+        lines: &[String], 
+        file_scope: bool,
+        keep: impl Fn(usize, &Span) -> bool
+    ) -> Vec<(Vec<Span>, bool)> {
+
+        let query = match &self.query {
+            Some(q) => q,
+            None => {
+                return lines
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, line)| {
+                        let mut spans = self.find_iter(line);
+                        spans.retain(|s| keep(idx, s));
+                        let is_match = !spans.is_empty();
+                        (spans, is_match)
+                    })
+                    .collect()
+            }
+        };
+
+        // Overlaps are resolved only after the hits are known, so a term 
+        // hidden inside a longer one still counts
+        let candidates: Vec<Vec<Span>> = lines
+            .iter()
+            .enumerate()
+            .map(|(idx, line)| {
+                let mut spans = self.find_candidates(line);
+                spans.retain(|s| keep(idx, s));
+                spans
+            })
+            .collect();
+        let hits: Vec<Vec<bool>> = candidates
+            .iter()
+            .map(|spans| {
+                let mut hit = vec![false; self.patterns.len()];
+                spans.iter().for_each(|s| hit[s.pattern] = true);
+                hit
+            })
+            .collect();
+
+        let positive = query.positive_terms();
+        let is_match: Vec<bool> = match file_scope {
+            false => query.matching_lines(&hits),
+            true => {
+                let file_matches = query.matches_file(&hits);
+                hits.iter()
+                    .map(|hit| {
+                        file_matches && hit.iter().zip(&positive).any(|(h, p)| *h && *p)
+                    })
+                    .collect()
+            }
+        };
+
+        candidates
+            .into_iter()
+            .zip(is_match)
+            .map(|(mut spans, is_match)| {
+                spans.retain(|s| positive[s.pattern]);
+                (self.resolve_overlaps(spans), is_match)
+            })
+            .collect()
+    }
+
+    // The line with each of the spans swapped for the expanded --replace
+    // template, and where the new text sits in the result. Groups in the
+    // template refer to a regex's groups; for other patterns only $0 means
+    // something.
+    pub fn replace_line(
+        &self, 
+        line: &str, 
+        spans: &[Span], 
+        template: &str
+    ) -> (String, Vec<Span>) {
+
+        let mut new_line = String::new();
+        let mut new_spans: Vec<Span> = Vec::new();
+        let mut last_idx: usize = 0;
+
+        for span in spans {
+            new_line.push_str(&line[last_idx..span.start]);
+
+            let captures = match &self.patterns[span.pattern] {
+                Pattern::Regex(re, 0) => re
+                    .captures_at(line, span.start)
+                    .filter(|slots| slots[0] == Some(span.start))
+                    .map(|slots| (re, slots)),
+                _ => None
+            };
+            let text = replace::expand(template, |key| match &captures {
+                Some((re, slots)) => {
+                    let group = re.group_index(key)?;
+                    Some(line[slots[group * 2]?..slots[group * 2 + 1]?].to_string())
+                },
+                None if key == "0" => Some(line[span.start..span.end].to_string()),
+                None => None
+            });
+
+            let start = new_line.len();
+            new_line.push_str(&text);
+            new_spans.push(Span { start, end: new_line.len(), ..*span });
+            last_idx = span.end;
+        }
+        new_line.push_str(&line[last_idx..]);
+        (new_line, new_spans)
+    }
+
+    fn find_candidates(&self, line: &str) -> Vec<Span> {
+
+        let mut spans: Vec<Span> = Vec::new();
+
+        if let Some(automaton) = &self.automaton {
+            spans = automaton.find_overlapping(line);
+            spans.retain(|s| self.boundary.accepts(line, s.start, s.end));
+        } else {
+            for (idx, pattern) in self.patterns.iter().enumerate() {
+                spans.extend(self.find_pattern(idx, pattern, line));
+            }
+        }
+        spans
+    }
+
+    fn resolve_overlaps(&self, mut spans: Vec<Span>) -> Vec<Span> {
+        if self.patterns.len() > 1 {
+            spans.sort_by_key(|s| (s.start, std::cmp::Reverse(s.end), s.pattern));
+            let mut kept: Vec<Span> = Vec::new();
+            for span in spans {
+                let overlaps = kept.last().is_some_and(|last| {
+                    span.start < last.end 
+                        || (span.start == last.start && span.end == last.end)
+                });
+                if !overlaps {
+                    kept.push(span);
+                }
+            }
+            spans = kept;
+        }
+        spans
+    }
+
+    fn find_pattern(&self, idx: usize, pattern: &Pattern, line: &str) -> Vec<Span> {
+        let accept = |s: usize, e: usize| self.boundary.accepts(line, s, e);
+        let found = match pattern {
+            Pattern::Literal(query) => {
+                let mut spans: Vec<(usize, usize)> = Vec::new();
+                let mut start: usize = 0;
+                while let Some(i) = line[start..].find(query.as_str()) {
+                    let (s, e) = (start + i, start + i + query.len());
+                    if accept(s, e) {
+                        spans.push((s, e));
+                        start = e;
+                    } else {
+                        // Try again from the next character, the match
+                        // might overlap with the one just rejected
+                        let c = line[s..].chars().next();
+                        start = s + c.map_or(1, char::len_utf8);
+                    }
+                }
+                spans
+            },
+            Pattern::FoldedLiteral(query) => {
+                casefold::find_iter(line, query, accept)
+            },
+            // Boundaries are compiled into the regex itself
+            Pattern::Regex(re, group) => re.find_group_iter(line, *group),
+            Pattern::Fuzzy(fuzzy) => {
+                return fuzzy.find_iter(line, accept)
+                    .into_iter()
+                    .map(|(start, end, distance)| {
+                        Span { start, end, pattern: idx, distance }
+                    })
+                    .collect()
+            }
+        };
+        found
+            .into_iter()
+            .map(|(start, end)| Span { start, end, pattern: idx, distance: 0 })
+            .collect()
+    }
+}
+
+
+fn fold_str(text: &str) -> Vec<char> {
+    text.chars().map(casefold::fold).collect()
+}
+
+// The text a regex matches if it has no special characters in it, e.g.
+// `print\(` is the literal `print(`
+fn regex_literal(query: &str) -> Option<String> {
+    let mut literal = String::new();
+    let mut chars = query.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(e) if !e.is_alphanumeric() => literal.push(e),
+                _ => return None
+            },
+            '.' | '+' | '*' | '?' | '(' | ')' | '|' | '[' | ']' 
+                | '{' | '}' | '^' | '$' => return None,
+            c => literal.push(c)
+        }
+    }
+    Some(literal)
+}
+
+// Whether smart case should match case-sensitively. In a regex, escapes 
+// such as \W or \S are not literal uppercase letters.
+fn has_uppercase(query: &str, regex: bool) -> bool {
+    let mut chars = query.chars();
+    while let Some(c) = chars.next() {
+        if regex && c == '\\' {
+            chars.next();
+        }
+        else if c.is_uppercase() {
+            return true
+        }
+    }
+    false
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn literal(query: &str, boundary: Boundary) -> Matcher {
+        Matcher { 
+            patterns: vec![Pattern::Literal(query.to_string())], 
+            automaton: None,
+            boundary,
+            query: None
+        }
+    }
+
+    fn spans(matcher: &Matcher, line: &str) -> Vec<(usize, usize)> {
+        matcher.find_iter(line).iter().map(|s| (s.start, s.end)).collect()
+    }
+
+    #[test]
+    fn whole_word_literals() {
+        let matcher = literal("print", Boundary::Word);
+        assert!(matcher.find_iter("printer(x)").is_empty());
+        assert_eq!(spans(&matcher, "reprint print(x)"), vec![(8, 13)]);
+
+        let matcher = Matcher {
+            patterns: vec![Pattern::FoldedLiteral("ökö".chars().collect())],
+            automaton: None,
+            boundary: Boundary::Word,
+            query: None
+        };
+        assert_eq!(spans(&matcher, "ÖKÖÖ ÖKÖ"), vec![(8, 13)]);
+    }
+
+    #[test]
+    fn whole_line_literals() {
+        let matcher = literal("lamb", Boundary::Line);
+        assert!(matcher.find_iter("a lamb").is_empty());
+        assert_eq!(spans(&matcher, "lamb"), vec![(0, 4)]);
+        assert_eq!(spans(&matcher, "a lamb\nlamb\nlambs"), vec![(7, 11)]);
+    }
+
+    #[test]
+    fn overlapping_patterns() {
+        let patterns = vec![
+            Pattern::Literal("print".to_string()),
+            Pattern::Literal("print(".to_string()),
+            Pattern::FoldedLiteral("debug".chars().collect())
+        ];
+        let expected = vec![
+            Span { start: 0, end: 6, pattern: 1, distance: 0 },
+            Span { start: 6, end: 11, pattern: 2, distance: 0 },
+            Span { start: 13, end: 18, pattern: 0, distance: 0 }
+        ];
+
+        // Searching each pattern in turn and through the automaton must 
+        // agree
+        let automaton = Some(Matcher::build_automaton(&patterns));
+        let looped = Matcher { 
+            patterns, 
+            automaton: None, 
+            boundary: Boundary::None,
+            query: None
+        };
+        assert_eq!(looped.find_iter("print(DEBUG) print"), expected);
+
+        let matcher = Matcher { 
+            patterns: looped.patterns, 
+            automaton, 
+            boundary: Boundary::None,
+            query: None
+        };
+        assert_eq!(matcher.find_iter("print(DEBUG) print"), expected);
+    }
+
+    #[test]
+    fn literal_regexes() {
+        assert_eq!(regex_literal(r"print\("), Some("print(".to_string()));
+        assert_eq!(regex_literal("console.log"), None);
+        assert_eq!(regex_literal(r"\w+"), None);
+    }
+
+    #[test]
+    fn smart_case_uppercase_detection() {
+        assert!(!has_uppercase("search_query", false));
+        assert!(has_uppercase("Search_Query", false));
+        assert!(!has_uppercase(r"\Wquery\S", true));
+        assert!(has_uppercase(r"\WQuery", true));
+    }
+}
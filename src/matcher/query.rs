@@ -0,0 +1,348 @@
+// Boolean queries such as `password AND NOT test` or
+// `unsafe NEAR/3 transmute`. The query is parsed into an expression tree
+// whose leaves are terms; each term is searched for like an ordinary
+// pattern and the tree is then evaluated over the lines of a file.
+//
+// Precedence, highest first: NOT, NEAR/n, AND, OR. Operands next to each
+// other without an operator are ANDed. Terms containing spaces, or that
+// would otherwise read as an operator, can be quoted: "NOT NULL".
+
+
+#[derive(Debug, PartialEq)]
+pub enum Expr {
+    Term(usize),  // index into BooleanQuery::terms
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Near(Box<Expr>, Box<Expr>, usize)
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Term(String),
+    And,
+    Or,
+    Not,
+    Near(usize),
+    Open,
+    Close
+}
+
+fn tokenize(query: &str) -> Result<Vec<Token>, String> {
+
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut chars = query.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        }
+        else if c == '(' || c == ')' {
+            chars.next();
+            tokens.push(if c == '(' { Token::Open } else { Token::Close });
+        }
+        else if c == '"' {
+            chars.next();
+            let mut term = String::new();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') if chars.peek() == Some(&'"') => {
+                        chars.next();
+                        term.push('"');
+                    },
+                    Some(ch) => term.push(ch),
+                    None => return Err(String::from("unclosed quote"))
+                }
+            }
+            tokens.push(Token::Term(term));
+        }
+        else {
+            let mut word = String::new();
+            while let Some(&ch) = chars.peek() {
+                if ch.is_whitespace() || ch == '(' || ch == ')' || ch == '"' {
+                    break
+                }
+                word.push(ch);
+                chars.next();
+            }
+            let token = match word.as_str() {
+                "AND" => Token::And,
+                "OR" => Token::Or,
+                "NOT" => Token::Not,
+                w if w.starts_with("NEAR") => match w.strip_prefix("NEAR/") {
+                    Some(n) => match n.parse::<usize>() {
+                        Ok(n) => Token::Near(n),
+                        Err(_) => return Err(format!("bad distance in {}", w))
+                    },
+                    None if w == "NEAR" => {
+                        return Err(String::from("NEAR needs a distance, e.g. NEAR/3"))
+                    },
+                    None => Token::Term(word)
+                },
+                _ => Token::Term(word)
+            };
+            tokens.push(token);
+        }
+    }
+    Ok(tokens)
+}
+
+
+pub struct BooleanQuery {
+    pub expr: Expr,
+    pub terms: Vec<String>
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    terms: Vec<String>
+}
+
+impl Parser {
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, String> {
+        let mut left = self.parse_and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.pos += 1;
+            let right = self.parse_and()?;
+            left = Expr::Or(Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, String> {
+        let mut left = self.parse_near()?;
+        loop {
+            match self.peek() {
+                Some(Token::And) => { self.pos += 1; },
+                // Implicit AND between neighbouring operands
+                Some(Token::Term(_)) | Some(Token::Not) | Some(Token::Open) => {},
+                _ => return Ok(left)
+            }
+            let right = self.parse_near()?;
+            left = Expr::And(Box::new(left), Box::new(right));
+        }
+    }
+
+    fn parse_near(&mut self) -> Result<Expr, String> {
+        let mut left = self.parse_not()?;
+        while let Some(Token::Near(n)) = self.peek() {
+            let n = *n;
+            self.pos += 1;
+            let right = self.parse_not()?;
+            left = Expr::Near(Box::new(left), Box::new(right), n);
+        }
+        Ok(left)
+    }
+
+    fn parse_not(&mut self) -> Result<Expr, String> {
+        if self.peek() == Some(&Token::Not) {
+            self.pos += 1;
+            return Ok(Expr::Not(Box::new(self.parse_not()?)))
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, String> {
+        let token = self.tokens.get(self.pos);
+        self.pos += 1;
+        match token {
+            Some(Token::Open) => {
+                let inner = self.parse_or()?;
+                match self.peek() {
+                    Some(Token::Close) => {
+                        self.pos += 1;
+                        Ok(inner)
+                    },
+                    _ => Err(String::from("unclosed parenthesis"))
+                }
+            },
+            Some(Token::Term(term)) => {
+                if term.is_empty() {
+                    return Err(String::from("empty term"))
+                }
+                let term = term.clone();
+                let idx = match self.terms.iter().position(|t| *t == term) {
+                    Some(i) => i,
+                    None => {
+                        self.terms.push(term);
+                        self.terms.len() - 1
+                    }
+                };
+                Ok(Expr::Term(idx))
+            },
+            Some(t) => Err(format!("unexpected {:?}", t)),
+            None => Err(String::from("query ends too early"))
+        }
+    }
+}
+
+impl BooleanQuery {
+
+    pub fn parse(query: &str) -> Result<Self, String> {
+        let mut parser = Parser { tokens: tokenize(query)?, pos: 0, terms: Vec::new() };
+        let expr = parser.parse_or()?;
+        if parser.pos < parser.tokens.len() {
+            return Err(format!("unexpected {:?}", parser.tokens[parser.pos]))
+        }
+        Ok(BooleanQuery { expr, terms: parser.terms })
+    }
+
+    // Terms that appear somewhere outside of a NOT, the ones worth
+    // highlighting
+    pub fn positive_terms(&self) -> Vec<bool> {
+        fn walk(expr: &Expr, negated: bool, out: &mut Vec<bool>) {
+            match expr {
+                Expr::Term(i) => if !negated { out[*i] = true },
+                Expr::Not(e) => walk(e, !negated, out),
+                Expr::And(a, b) | Expr::Or(a, b) | Expr::Near(a, b, _) => {
+                    walk(a, negated, out);
+                    walk(b, negated, out);
+                }
+            }
+        }
+        let mut out = vec![false; self.terms.len()];
+        walk(&self.expr, false, &mut out);
+        out
+    }
+
+    // `hits[line][term]` says whether a term occurs on a line. Returns which
+    // lines satisfy the query on their own, with NEAR/n looking at the
+    // lines around them.
+    pub fn matching_lines(&self, hits: &[Vec<bool>]) -> Vec<bool> {
+        Self::eval_lines(&self.expr, hits)
+    }
+
+    // Whether the file as a whole satisfies the query: a term is true if it
+    // occurs on any line.
+    pub fn matches_file(&self, hits: &[Vec<bool>]) -> bool {
+        Self::eval_file(&self.expr, hits)
+    }
+
+    fn eval_lines(expr: &Expr, hits: &[Vec<bool>]) -> Vec<bool> {
+        match expr {
+            Expr::Term(i) => hits.iter().map(|line| line[*i]).collect(),
+            Expr::Not(e) => {
+                Self::eval_lines(e, hits).into_iter().map(|v| !v).collect()
+            },
+            Expr::And(a, b) | Expr::Or(a, b) => {
+                let left = Self::eval_lines(a, hits);
+                let right = Self::eval_lines(b, hits);
+                let is_and = matches!(expr, Expr::And(..));
+                left.iter()
+                    .zip(right)
+                    .map(|(l, r)| if is_and { *l && r } else { *l || r })
+                    .collect()
+            },
+            Expr::Near(a, b, n) => {
+                let left = Self::eval_lines(a, hits);
+                let right = Self::eval_lines(b, hits);
+                let left_near = within(&left, *n);
+                let right_near = within(&right, *n);
+                (0..hits.len())
+                    .map(|i| (left[i] && right_near[i]) || (right[i] && left_near[i]))
+                    .collect()
+            }
+        }
+    }
+
+    fn eval_file(expr: &Expr, hits: &[Vec<bool>]) -> bool {
+        match expr {
+            Expr::Term(i) => hits.iter().any(|line| line[*i]),
+            Expr::Not(e) => !Self::eval_file(e, hits),
+            Expr::And(a, b) => Self::eval_file(a, hits) && Self::eval_file(b, hits),
+            Expr::Or(a, b) => Self::eval_file(a, hits) || Self::eval_file(b, hits),
+            Expr::Near(..) => Self::eval_lines(expr, hits).contains(&true)
+        }
+    }
+}
+
+// For each line, whether any of `lines` is true within `n` lines of it
+fn within(lines: &[bool], n: usize) -> Vec<bool> {
+    let mut prefix: Vec<usize> = vec![0; lines.len() + 1];
+    for (i, v) in lines.iter().enumerate() {
+        prefix[i + 1] = prefix[i] + usize::from(*v);
+    }
+    (0..lines.len())
+        .map(|i| {
+            let lo = i.saturating_sub(n);
+            let hi = (i + n + 1).min(lines.len());
+            prefix[hi] - prefix[lo] > 0
+        })
+        .collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn parse(query: &str) -> BooleanQuery {
+        match BooleanQuery::parse(query) {
+            Ok(q) => q,
+            Err(msg) => panic!("Failed to parse {}: {}", query, msg)
+        }
+    }
+
+    #[test]
+    fn precedence_and_implicit_and() {
+        let q = parse("a OR b c AND NOT d");
+        let expected = Expr::Or(
+            Box::new(Expr::Term(0)),
+            Box::new(Expr::And(
+                Box::new(Expr::And(Box::new(Expr::Term(1)), Box::new(Expr::Term(2)))),
+                Box::new(Expr::Not(Box::new(Expr::Term(3))))
+            ))
+        );
+        assert_eq!(q.expr, expected);
+        assert_eq!(q.terms, vec!["a", "b", "c", "d"]);
+        assert_eq!(q.positive_terms(), vec![true, true, true, false]);
+    }
+
+    #[test]
+    fn quoted_terms_and_errors() {
+        let q = parse(r#""NOT NULL" OR "say \"hi\"""#);
+        assert_eq!(q.terms, vec!["NOT NULL", r#"say "hi""#]);
+        for bad in ["(a OR b", "a AND", "NEAR b", "a NEAR/x b", "\"a", ")"] {
+            assert!(BooleanQuery::parse(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn line_and_file_evaluation() {
+        // terms: password, test
+        let q = parse("password AND NOT test");
+        let hits = vec![
+            vec![true, false],
+            vec![true, true],
+            vec![false, true]
+        ];
+        assert_eq!(q.matching_lines(&hits), vec![true, false, false]);
+        assert!(!q.matches_file(&hits));
+    }
+
+    #[test]
+    fn near_evaluation() {
+        let q = parse("unsafe NEAR/2 transmute");
+        let hits = vec![
+            vec![true, false],
+            vec![false, false],
+            vec![false, true],
+            vec![false, false],
+            vec![false, false],
+            vec![true, false]
+        ];
+        assert_eq!(
+            q.matching_lines(&hits),
+            vec![true, false, true, false, false, false]
+        );
+        assert!(q.matches_file(&hits));
+    }
+}
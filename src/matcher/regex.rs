@@ -0,0 +1,955 @@
+// A small regular expression engine. Patterns are parsed into a syntax tree,
+// compiled into a list of instructions and executed with a Pike VM, so the
+// time spent on a line grows linearly with its length however the pattern
+// is written (no catastrophic backtracking on things like `(a*)*b`).
+//
+// Supported syntax:
+//     .  [abc]  [^a-z]  \d \w \s \D \W \S     characters and classes
+//     ^  $  \b  \B  \A  \z                    anchors and assertions
+//     a|b  (...)  (?:...)  (?P<name>...)      alternation and groups
+//     *  +  ?  {n}  {n,}  {n,m}               repetition (add `?` for lazy)
+
+use crate::matcher::{casefold, Boundary};
+
+const MAX_REPEAT: u32 = 1000;
+const MAX_PROGRAM_SIZE: usize = 100_000;
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Assertion {
+    LineStart,
+    LineEnd,
+    TextStart,
+    TextEnd,
+    WordBoundary,
+    NotWordBoundary,
+    NoWordBefore,  // used by -w, the match mustn't extend a word
+    NoWordAfter
+}
+
+impl Assertion {
+    fn holds(&self, prev: Option<char>, next: Option<char>) -> bool {
+        match self {
+            Self::LineStart => matches!(prev, None | Some('\n')),
+            Self::LineEnd => matches!(next, None | Some('\n')),
+            Self::TextStart => prev.is_none(),
+            Self::TextEnd => next.is_none(),
+            Self::WordBoundary => {
+                prev.is_some_and(is_word_char) != next.is_some_and(is_word_char)
+            },
+            Self::NotWordBoundary => {
+                prev.is_some_and(is_word_char) == next.is_some_and(is_word_char)
+            },
+            Self::NoWordBefore => !prev.is_some_and(is_word_char),
+            Self::NoWordAfter => !next.is_some_and(is_word_char)
+        }
+    }
+}
+
+pub fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum PerlClass {
+    Digit,
+    Word,
+    Space
+}
+
+impl PerlClass {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Self::Digit => c.is_numeric(),
+            Self::Word => is_word_char(c),
+            Self::Space => c.is_whitespace()
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum ClassItem {
+    Range(char, char),
+    Perl(PerlClass, bool)  // bool: negated, e.g. \D
+}
+
+impl ClassItem {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Self::Range(lo, hi) => *lo <= c && c <= *hi,
+            Self::Perl(class, negated) => class.matches(c) != *negated
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct CharClass {
+    items: Vec<ClassItem>,
+    negated: bool
+}
+
+impl CharClass {
+    fn perl(class: PerlClass, negated: bool) -> Self {
+        CharClass { items: vec![ClassItem::Perl(class, negated)], negated: false }
+    }
+
+    fn matches(&self, c: char, case_insensitive: bool) -> bool {
+        let mut hit = self.items.iter().any(|item| item.matches(c));
+        if !hit && case_insensitive {
+            hit = casefold::variants(c)
+                .into_iter()
+                .any(|v| self.items.iter().any(|item| item.matches(v)));
+        }
+        hit != self.negated
+    }
+}
+
+
+// ------------------------------------------------------------------------ //
+//                                 PARSING                                   //
+// ------------------------------------------------------------------------ //
+#[derive(Debug)]
+enum Node {
+    Empty,
+    Literal(char),
+    Any,
+    Class(CharClass),
+    Assert(Assertion),
+    Group(Box<Node>, Option<usize>),  // capture index, None if non-capturing
+    Concat(Vec<Node>),
+    Alternate(Vec<Node>),
+    Repeat {
+        node: Box<Node>,
+        min: u32,
+        max: Option<u32>,
+        greedy: bool
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    names: Vec<Option<String>>
+}
+
+impl Parser {
+
+    fn new(pattern: &str) -> Self {
+        Parser {
+            chars: pattern.chars().collect(),
+            pos: 0,
+            names: vec![None]  // group 0 is the whole match
+        }
+    }
+
+    fn error(&self, msg: &str) -> String {
+        format!("{} at position {}", msg, self.pos)
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek();
+        if c.is_some() {
+            self.pos += 1;
+        }
+        c
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn eat_str(&mut self, s: &str) -> bool {
+        let wanted: Vec<char> = s.chars().collect();
+        let end = self.pos + wanted.len();
+        if end <= self.chars.len() && self.chars[self.pos..end] == wanted[..] {
+            self.pos = end;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn parse(&mut self) -> Result<Node, String> {
+        let node = self.parse_alternation()?;
+        match self.peek() {
+            None => Ok(node),
+            Some(_) => Err(self.error("unopened group")) // stray ')'
+        }
+    }
+
+    fn parse_alternation(&mut self) -> Result<Node, String> {
+        let mut branches = vec![self.parse_concat()?];
+        while self.eat('|') {
+            branches.push(self.parse_concat()?);
+        }
+        if branches.len() == 1 {
+            Ok(branches.remove(0))
+        } else {
+            Ok(Node::Alternate(branches))
+        }
+    }
+
+    fn parse_concat(&mut self) -> Result<Node, String> {
+        let mut items: Vec<Node> = Vec::new();
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break
+            }
+            let atom = self.parse_atom()?;
+            items.push(self.parse_repetition(atom)?);
+        }
+        match items.len() {
+            0 => Ok(Node::Empty),
+            1 => Ok(items.remove(0)),
+            _ => Ok(Node::Concat(items))
+        }
+    }
+
+    fn parse_atom(&mut self) -> Result<Node, String> {
+        let c = match self.next() {
+            Some(c) => c,
+            None => return Err(self.error("unexpected end of pattern"))
+        };
+        match c {
+            '(' => self.parse_group(),
+            '[' => Ok(Node::Class(self.parse_class()?)),
+            '.' => Ok(Node::Any),
+            '^' => Ok(Node::Assert(Assertion::LineStart)),
+            '$' => Ok(Node::Assert(Assertion::LineEnd)),
+            '\\' => self.parse_escape(),
+            '*' | '+' | '?' => {
+                self.pos -= 1;
+                Err(self.error("repetition operator missing expression"))
+            },
+            '{' => {
+                self.pos -= 1;
+                if self.parse_counted()?.is_some() {
+                    return Err(self.error("repetition operator missing expression"))
+                }
+                self.pos += 1;
+                Ok(Node::Literal('{'))
+            },
+            c => Ok(Node::Literal(c))
+        }
+    }
+
+    fn parse_group(&mut self) -> Result<Node, String> {
+        let index = if self.eat_str("?:") {
+            None
+        } else if self.eat_str("?P<") || self.eat_str("?<") {
+            if matches!(self.peek(), Some('=') | Some('!')) {
+                return Err(self.error("look-around is not supported"))
+            }
+            let mut name = String::new();
+            loop {
+                match self.next() {
+                    Some('>') => break,
+                    Some(c) if is_word_char(c) => name.push(c),
+                    _ => return Err(self.error("invalid capture group name"))
+                }
+            }
+            if name.is_empty() || self.names.contains(&Some(name.clone())) {
+                return Err(self.error("invalid capture group name"))
+            }
+            self.names.push(Some(name));
+            Some(self.names.len() - 1)
+        } else if self.peek() == Some('?') {
+            return Err(self.error("unsupported group syntax"))
+        } else {
+            self.names.push(None);
+            Some(self.names.len() - 1)
+        };
+
+        let node = self.parse_alternation()?;
+        if !self.eat(')') {
+            return Err(self.error("unclosed group"))
+        }
+        Ok(Node::Group(Box::new(node), index))
+    }
+
+    fn parse_escape(&mut self) -> Result<Node, String> {
+        let c = match self.next() {
+            Some(c) => c,
+            None => return Err(self.error("trailing backslash"))
+        };
+        let node = match c {
+            'd' => Node::Class(CharClass::perl(PerlClass::Digit, false)),
+            'D' => Node::Class(CharClass::perl(PerlClass::Digit, true)),
+            'w' => Node::Class(CharClass::perl(PerlClass::Word, false)),
+            'W' => Node::Class(CharClass::perl(PerlClass::Word, true)),
+            's' => Node::Class(CharClass::perl(PerlClass::Space, false)),
+            'S' => Node::Class(CharClass::perl(PerlClass::Space, true)),
+            'b' => Node::Assert(Assertion::WordBoundary),
+            'B' => Node::Assert(Assertion::NotWordBoundary),
+            'A' => Node::Assert(Assertion::TextStart),
+            'z' => Node::Assert(Assertion::TextEnd),
+            _ => {
+                self.pos -= 1;
+                Node::Literal(self.parse_escaped_char()?)
+            }
+        };
+        Ok(node)
+    }
+
+    // Escapes that stand for a single character, valid both inside and
+    // outside of a class.
+    fn parse_escaped_char(&mut self) -> Result<char, String> {
+        let c = match self.next() {
+            Some(c) => c,
+            None => return Err(self.error("trailing backslash"))
+        };
+        match c {
+            'n' => Ok('\n'),
+            't' => Ok('\t'),
+            'r' => Ok('\r'),
+            'f' => Ok('\x0c'),
+            'v' => Ok('\x0b'),
+            '0' => Ok('\0'),
+            'x' => self.parse_hex(),
+            c if !c.is_alphanumeric() => Ok(c),
+            c if c.is_ascii_digit() => {
+                Err(self.error("backreferences are not supported"))
+            },
+            c => Err(self.error(&format!("unrecognized escape \\{}", c)))
+        }
+    }
+
+    fn parse_hex(&mut self) -> Result<char, String> {
+        let mut digits = String::new();
+        if self.eat('{') {
+            loop {
+                match self.next() {
+                    Some('}') => break,
+                    Some(c) => digits.push(c),
+                    None => return Err(self.error("unclosed hex escape"))
+                }
+            }
+        } else {
+            for _ in 0..2 {
+                if let Some(c) = self.next() {
+                    digits.push(c);
+                }
+            }
+        }
+        u32::from_str_radix(&digits, 16)
+            .ok()
+            .and_then(char::from_u32)
+            .ok_or_else(|| self.error("invalid hex escape"))
+    }
+
+    fn parse_class(&mut self) -> Result<CharClass, String> {
+        let negated = self.eat('^');
+        let mut items: Vec<ClassItem> = Vec::new();
+        let mut first = true;
+
+        loop {
+            let c = match self.next() {
+                Some(c) => c,
+                None => return Err(self.error("unclosed character class"))
+            };
+            if c == ']' && !first {
+                break
+            }
+            first = false;
+
+            let start = if c == '\\' {
+                let perl = match self.peek() {
+                    Some('d') => Some((PerlClass::Digit, false)),
+                    Some('D') => Some((PerlClass::Digit, true)),
+                    Some('w') => Some((PerlClass::Word, false)),
+                    Some('W') => Some((PerlClass::Word, true)),
+                    Some('s') => Some((PerlClass::Space, false)),
+                    Some('S') => Some((PerlClass::Space, true)),
+                    _ => None
+                };
+                if let Some((class, neg)) = perl {
+                    self.pos += 1;
+                    items.push(ClassItem::Perl(class, neg));
+                    continue
+                }
+                self.parse_escaped_char()?
+            } else {
+                c
+            };
+
+            // A '-' is a range only when it sits between two characters
+            let is_range = self.peek() == Some('-')
+                && !matches!(self.chars.get(self.pos + 1), Some(']') | None);
+
+            if is_range {
+                self.pos += 1;
+                let end = match self.next() {
+                    Some('\\') => self.parse_escaped_char()?,
+                    Some(e) => e,
+                    None => return Err(self.error("unclosed character class"))
+                };
+                if end < start {
+                    return Err(self.error("invalid class range"))
+                }
+                items.push(ClassItem::Range(start, end));
+            } else {
+                items.push(ClassItem::Range(start, start));
+            }
+        }
+        Ok(CharClass { items, negated })
+    }
+
+    fn parse_repetition(&mut self, mut atom: Node) -> Result<Node, String> {
+        loop {
+            let (min, max) = match self.peek() {
+                Some('*') => { self.pos += 1; (0, None) },
+                Some('+') => { self.pos += 1; (1, None) },
+                Some('?') => { self.pos += 1; (0, Some(1)) },
+                Some('{') => match self.parse_counted()? {
+                    Some(bounds) => bounds,
+                    None => return Ok(atom)
+                },
+                _ => return Ok(atom)
+            };
+            let greedy = !self.eat('?');
+            atom = Node::Repeat { node: Box::new(atom), min, max, greedy };
+        }
+    }
+
+    // Parses `{n}`, `{n,}` or `{n,m}`. Anything else starting with `{` is
+    // left alone (and later read as a literal brace).
+    fn parse_counted(&mut self) -> Result<Option<(u32, Option<u32>)>, String> {
+        let start = self.pos;
+        self.pos += 1;
+
+        // A count too big for a u32 is still a count, and is caught by the
+        // MAX_REPEAT check below rather than read as a literal
+        let read_number = |p: &mut Parser| -> Option<u32> {
+            let mut digits = String::new();
+            while let Some(c) = p.peek().filter(char::is_ascii_digit) {
+                digits.push(c);
+                p.pos += 1;
+            }
+            match digits.is_empty() {
+                true => None,
+                false => Some(digits.parse::<u32>().unwrap_or(u32::MAX))
+            }
+        };
+
+        let min = read_number(self);
+        let bounds = match (min, self.next()) {
+            (Some(n), Some('}')) => Some((n, Some(n))),
+            (Some(n), Some(',')) => {
+                let max = read_number(self);
+                match self.next() {
+                    Some('}') => Some((n, max)),
+                    _ => None
+                }
+            },
+            _ => None
+        };
+
+        match bounds {
+            None => {
+                self.pos = start;
+                Ok(None)
+            },
+            Some((min, max)) => {
+                if max.is_some_and(|m| m < min) {
+                    return Err(self.error("invalid repetition range"))
+                }
+                if min > MAX_REPEAT || max.is_some_and(|m| m > MAX_REPEAT) {
+                    return Err(self.error("repetition count too large"))
+                }
+                Ok(Some((min, max)))
+            }
+        }
+    }
+}
+
+
+// ------------------------------------------------------------------------ //
+//                                COMPILING                                  //
+// ------------------------------------------------------------------------ //
+#[derive(Debug)]
+enum Inst {
+    Char(char),
+    Any,
+    Class(CharClass),
+    Assert(Assertion),
+    Save(usize),
+    Split(usize, usize),  // try the first branch, then the second
+    Jmp(usize),
+    Match
+}
+
+struct Compiler {
+    prog: Vec<Inst>,
+    case_insensitive: bool
+}
+
+impl Compiler {
+
+    fn emit(&mut self, inst: Inst) -> Result<usize, String> {
+        if self.prog.len() >= MAX_PROGRAM_SIZE {
+            return Err(String::from("pattern is too large"))
+        }
+        self.prog.push(inst);
+        Ok(self.prog.len() - 1)
+    }
+
+    fn patch_split(&mut self, at: usize, first: usize, second: usize) {
+        self.prog[at] = Inst::Split(first, second);
+    }
+
+    fn compile(&mut self, node: &Node) -> Result<(), String> {
+        match node {
+            Node::Empty => {},
+            Node::Literal(c) => {
+                let c = if self.case_insensitive { casefold::fold(*c) } else { *c };
+                self.emit(Inst::Char(c))?;
+            },
+            Node::Any => { self.emit(Inst::Any)?; },
+            Node::Class(class) => { self.emit(Inst::Class(class.clone()))?; },
+            Node::Assert(a) => { self.emit(Inst::Assert(*a))?; },
+            Node::Group(inner, index) => match index {
+                Some(i) => {
+                    self.emit(Inst::Save(2 * i))?;
+                    self.compile(inner)?;
+                    self.emit(Inst::Save(2 * i + 1))?;
+                },
+                None => self.compile(inner)?
+            },
+            Node::Concat(nodes) => {
+                for n in nodes {
+                    self.compile(n)?;
+                }
+            },
+            Node::Alternate(branches) => {
+                let mut jumps: Vec<usize> = Vec::new();
+                for (i, branch) in branches.iter().enumerate() {
+                    if i == branches.len() - 1 {
+                        self.compile(branch)?;
+                    } else {
+                        let split = self.emit(Inst::Split(0, 0))?;
+                        self.compile(branch)?;
+                        jumps.push(self.emit(Inst::Jmp(0))?);
+                        let next = self.prog.len();
+                        self.patch_split(split, split + 1, next);
+                    }
+                }
+                let end = self.prog.len();
+                for j in jumps {
+                    self.prog[j] = Inst::Jmp(end);
+                }
+            },
+            Node::Repeat { node, min, max, greedy } => {
+                for _ in 0..*min {
+                    self.compile(node)?;
+                }
+                match max {
+                    None => {
+                        let split = self.emit(Inst::Split(0, 0))?;
+                        self.compile(node)?;
+                        self.emit(Inst::Jmp(split))?;
+                        let out = self.prog.len();
+                        match greedy {
+                            true => self.patch_split(split, split + 1, out),
+                            false => self.patch_split(split, out, split + 1)
+                        }
+                    },
+                    Some(max) => {
+                        let mut splits: Vec<usize> = Vec::new();
+                        for _ in *min..*max {
+                            splits.push(self.emit(Inst::Split(0, 0))?);
+                            self.compile(node)?;
+                        }
+                        let out = self.prog.len();
+                        for split in splits {
+                            match greedy {
+                                true => self.patch_split(split, split + 1, out),
+                                false => self.patch_split(split, out, split + 1)
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+
+// ------------------------------------------------------------------------ //
+//                                 MATCHING                                  //
+// ------------------------------------------------------------------------ //
+type Slots = Vec<Option<usize>>;
+
+struct ThreadList {
+    seen: Vec<usize>,
+    generation: usize,
+    threads: Vec<(usize, Slots)>
+}
+
+impl ThreadList {
+
+    fn new(size: usize) -> Self {
+        ThreadList { seen: vec![0; size], generation: 1, threads: Vec::new() }
+    }
+
+    fn clear(&mut self) {
+        self.generation += 1;
+        self.threads.clear();
+    }
+
+    // Returns false if the instruction was already visited at this position
+    fn visit(&mut self, pc: usize) -> bool {
+        if self.seen[pc] == self.generation {
+            false
+        } else {
+            self.seen[pc] = self.generation;
+            true
+        }
+    }
+}
+
+pub struct Regex {
+    prog: Vec<Inst>,
+    names: Vec<Option<String>>,
+    case_insensitive: bool
+}
+
+impl Regex {
+
+    pub fn new(pattern: &str, case_insensitive: bool) -> Result<Self, String> {
+        Regex::with_boundary(pattern, case_insensitive, Boundary::None)
+    }
+
+    // Compiles the pattern so that matches must also sit on word or line
+    // boundaries at both ends
+    pub fn with_boundary(
+        pattern: &str, 
+        case_insensitive: bool, 
+        boundary: Boundary
+    ) -> Result<Self, String> {
+        let mut parser = Parser::new(pattern);
+        let tree = parser.parse()?;
+
+        let (before, after) = match boundary {
+            Boundary::None => (None, None),
+            Boundary::Word => {
+                (Some(Assertion::NoWordBefore), Some(Assertion::NoWordAfter))
+            },
+            Boundary::Line => {
+                (Some(Assertion::LineStart), Some(Assertion::LineEnd))
+            }
+        };
+
+        let mut compiler = Compiler { prog: Vec::new(), case_insensitive };
+        compiler.emit(Inst::Save(0))?;
+        if let Some(a) = before {
+            compiler.emit(Inst::Assert(a))?;
+        }
+        compiler.compile(&tree)?;
+        if let Some(a) = after {
+            compiler.emit(Inst::Assert(a))?;
+        }
+        compiler.emit(Inst::Save(1))?;
+        compiler.emit(Inst::Match)?;
+
+        Ok(Regex { prog: compiler.prog, names: parser.names, case_insensitive })
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        self.find_at(text, 0).is_some()
+    }
+
+    pub fn find(&self, text: &str) -> Option<(usize, usize)> {
+        self.find_at(text, 0)
+    }
+
+    // Every non-overlapping match, left to right. An empty match directly 
+    // after the previous match is skipped.
+    pub fn find_iter(&self, text: &str) -> Vec<(usize, usize)> {
+        self.find_group_iter(text, 0)
+    }
+
+    // Like `find_iter`, but the span of capture group `group` in each match.
+    // Matches the group took no part in are left out.
+    pub fn find_group_iter(&self, text: &str, group: usize) -> Vec<(usize, usize)> {
+        let mut spans: Vec<(usize, usize)> = Vec::new();
+        let mut last_end: Option<usize> = None;
+        let mut start: usize = 0;
+
+        while start <= text.len() {
+            let slots = match self.captures_at(text, start) {
+                Some(slots) => slots,
+                None => break
+            };
+            let (s, e) = match (slots[0], slots[1]) {
+                (Some(s), Some(e)) => (s, e),
+                _ => break
+            };
+            if (s < e || last_end != Some(s))
+                && let (Some(gs), Some(ge)) = (slots[group * 2], slots[group * 2 + 1]) {
+                spans.push((gs, ge));
+            }
+            if s < e {
+                last_end = Some(e);
+            }
+            start = match (s < e, text[e..].chars().next()) {
+                (true, _) => e,
+                (false, Some(c)) => e + c.len_utf8(),
+                (false, None) => break
+            };
+        }
+        spans
+    }
+
+    // Index of a capture group given by its number or its name
+    pub fn group_index(&self, group: &str) -> Option<usize> {
+        match group.parse::<usize>() {
+            Ok(n) => Some(n).filter(|n| *n < self.names.len()),
+            Err(_) => self.names.iter().position(|n| n.as_deref() == Some(group))
+        }
+    }
+
+    pub fn find_at(&self, text: &str, start: usize) -> Option<(usize, usize)> {
+        match self.captures_at(text, start) {
+            Some(slots) => Some((slots[0]?, slots[1]?)),
+            None => None
+        }
+    }
+
+    // Leftmost-first match starting the search at byte offset `start`.
+    // Returns the start and end offsets of every group; group 0 is the
+    // whole match.
+    pub fn captures_at(&self, text: &str, start: usize) -> Option<Slots> {
+
+        let num_slots = self.names.len() * 2;
+        let mut clist = ThreadList::new(self.prog.len());
+        let mut nlist = ThreadList::new(self.prog.len());
+        let mut matched: Option<Slots> = None;
+
+        let mut chars = text[start..]
+            .char_indices()
+            .map(|(i, c)| (i + start, c))
+            .peekable();
+        let mut prev: Option<char> = text[..start].chars().next_back();
+        let mut pos = start;
+
+        loop {
+            let current = chars.next().map(|(_, c)| c);
+            let next = chars.peek().map(|(_, c)| *c);
+
+            // Lowest priority: a new attempt starting at this position
+            if matched.is_none() {
+                self.add_thread(
+                    &mut clist, 0, pos, prev, current, vec![None; num_slots]
+                );
+            }
+            if clist.threads.is_empty() && matched.is_some() {
+                break
+            }
+
+            let next_pos = pos + current.map_or(0, char::len_utf8);
+            let threads = std::mem::take(&mut clist.threads);
+
+            for (pc, slots) in threads {
+                let advances = match (&self.prog[pc], current) {
+                    (Inst::Match, _) => {
+                        matched = Some(slots);
+                        break  // threads after this one have lower priority
+                    },
+                    (Inst::Char(c), Some(h)) => match self.case_insensitive {
+                        true => *c == casefold::fold(h),
+                        false => *c == h
+                    },
+                    (Inst::Any, Some(h)) => h != '\n',
+                    (Inst::Class(class), Some(h)) => {
+                        class.matches(h, self.case_insensitive)
+                    },
+                    _ => false
+                };
+                if advances {
+                    self.add_thread(
+                        &mut nlist, pc + 1, next_pos, current, next, slots
+                    );
+                }
+            }
+
+            if current.is_none() {
+                break
+            }
+            std::mem::swap(&mut clist, &mut nlist);
+            nlist.clear();
+            prev = current;
+            pos = next_pos;
+        }
+
+        matched
+    }
+
+    // Follows every instruction that doesn't consume a character and queues
+    // the ones that do, keeping the priority order of the branches.
+    fn add_thread(
+        &self,
+        list: &mut ThreadList,
+        pc: usize,
+        pos: usize,
+        prev: Option<char>,
+        next: Option<char>,
+        slots: Slots
+    ) {
+        let mut stack: Vec<(usize, Slots)> = vec![(pc, slots)];
+
+        while let Some((pc, mut slots)) = stack.pop() {
+            if !list.visit(pc) {
+                continue
+            }
+            match &self.prog[pc] {
+                Inst::Jmp(target) => stack.push((*target, slots)),
+                Inst::Split(first, second) => {
+                    stack.push((*second, slots.clone()));
+                    stack.push((*first, slots));
+                },
+                Inst::Save(slot) => {
+                    slots[*slot] = Some(pos);
+                    stack.push((pc + 1, slots));
+                },
+                Inst::Assert(assertion) => {
+                    if assertion.holds(prev, next) {
+                        stack.push((pc + 1, slots));
+                    }
+                },
+                _ => list.threads.push((pc, slots))
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn find(pattern: &str, text: &str) -> Option<(usize, usize)> {
+        match Regex::new(pattern, false) {
+            Ok(re) => re.find(text),
+            Err(msg) => panic!("Failed to compile {}: {}", pattern, msg)
+        }
+    }
+
+    #[test]
+    fn literal_and_classes() {
+        assert_eq!(find("lamb", "Mary had a little lamb"), Some((18, 22)));
+        assert_eq!(find("[0-9]+", "version 1.42"), Some((8, 9)));
+        assert_eq!(find(r"\d+\.\d+", "version 1.42"), Some((8, 12)));
+        assert_eq!(find("[^a-z ]", "abc Def"), Some((4, 5)));
+        assert_eq!(find(r"\w+", "  héllo_1 "), Some((2, 10)));
+    }
+
+    #[test]
+    fn anchors_and_alternation() {
+        assert_eq!(find(r"print\(.*\)$", "    print(x)"), Some((4, 12)));
+        assert_eq!(find(r"print\(.*\)$", "print(x) # done"), None);
+        assert_eq!(find("^cat|dog", "hotdog"), Some((3, 6)));
+        assert_eq!(find(r"\bcat\b", "concat cat"), Some((7, 10)));
+    }
+
+    #[test]
+    fn repetition_and_captures() {
+        assert_eq!(find("a{2,3}", "caaaat"), Some((1, 4)));
+        assert_eq!(find("a+?", "aaa"), Some((0, 1)));
+        assert_eq!(find("(a*)*b", "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaac"), None);
+
+        let re = match Regex::new(r"TODO\((?P<who>\w+)\)", false) {
+            Ok(r) => r,
+            Err(msg) => panic!("{}", msg)
+        };
+        let slots = re.captures_at("// TODO(dpad): fix", 0).unwrap();
+        assert_eq!(slots[0], Some(3));
+        assert_eq!((slots[2], slots[3]), (Some(8), Some(12)));
+    }
+
+    #[test]
+    fn find_every_match() {
+        let re = match Regex::new(r"\d+", false) {
+            Ok(r) => r,
+            Err(msg) => panic!("{}", msg)
+        };
+        assert_eq!(re.find_iter("a1 b22 c333"), vec![(1, 2), (4, 6), (8, 11)]);
+
+        let re = match Regex::new("x*", false) {
+            Ok(r) => r,
+            Err(msg) => panic!("{}", msg)
+        };
+        assert_eq!(re.find_iter("axxb"), vec![(0, 0), (1, 3), (4, 4)]);
+
+        let re = match Regex::new(r"(?P<key>\w+)=(\d+)?", false) {
+            Ok(r) => r,
+            Err(msg) => panic!("{}", msg)
+        };
+        assert_eq!(re.group_index("key"), Some(1));
+        assert_eq!(re.group_index("2"), Some(2));
+        assert_eq!(re.group_index("3"), None);
+        assert_eq!(re.find_group_iter("a=1 b= c=22", 2), vec![(2, 3), (9, 11)]);
+    }
+
+    #[test]
+    fn word_and_line_boundaries() {
+        let re = match Regex::with_boundary("foo|foobar", false, Boundary::Word) {
+            Ok(r) => r,
+            Err(msg) => panic!("{}", msg)
+        };
+        assert_eq!(re.find("foobar"), Some((0, 6)));
+        assert_eq!(re.find("xfoobar"), None);
+
+        let re = match Regex::with_boundary(r"-\w+", false, Boundary::Word) {
+            Ok(r) => r,
+            Err(msg) => panic!("{}", msg)
+        };
+        assert_eq!(re.find("ls -la"), Some((3, 6)));
+
+        let re = match Regex::with_boundary("a+", false, Boundary::Line) {
+            Ok(r) => r,
+            Err(msg) => panic!("{}", msg)
+        };
+        assert_eq!(re.find("aaa"), Some((0, 3)));
+        assert_eq!(re.find("aaab"), None);
+    }
+
+    #[test]
+    fn case_insensitive() {
+        let re = match Regex::new("ma[r]y", true) {
+            Ok(r) => r,
+            Err(msg) => panic!("{}", msg)
+        };
+        assert_eq!(re.find("MARY"), Some((0, 4)));
+
+        let re = match Regex::new("[α-ω]+ς", true) {
+            Ok(r) => r,
+            Err(msg) => panic!("{}", msg)
+        };
+        assert_eq!(re.find("ΟΔΟΣ"), Some((0, 8)));
+    }
+
+    #[test]
+    fn invalid_patterns() {
+        for pattern in ["(abc", "abc)", "[a-", "*a", r"\q", "a{3,1}"] {
+            assert!(Regex::new(pattern, false).is_err(), "{}", pattern);
+        }
+        for pattern in ["a{1001}", "a{99999999999}", "a{1,99999999999}", "a{99999999999,}"] {
+            let too_large = Regex::new(pattern, false)
+                .err()
+                .is_some_and(|e| e.contains("repetition count too large"));
+            assert!(too_large, "{}", pattern);
+        }
+    }
+}
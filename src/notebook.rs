@@ -0,0 +1,138 @@
+// Jupyter notebooks, read into their cells so each cell's source (and, with
+// --notebook-outputs, what it printed) can be searched as text of its own.
+
+use crate::json::{self, Value};
+
+
+pub struct Cell {
+    pub number: usize,      // counting from 1, in notebook order
+    pub kind: String,       // code, markdown or raw
+    pub source: String,
+    pub outputs: String
+}
+
+pub struct Notebook {
+    // The extension of the kernel's language, e.g. "py", for the lexer
+    pub extension: String,
+    pub cells: Vec<Cell>
+}
+
+impl Notebook {
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+
+        let value = json::parse(text)?;
+        let cells = match value.get("cells").and_then(Value::as_array) {
+            Some(c) => c,
+            None => return Err(String::from("not a notebook: it has no cells"))
+        };
+
+        let metadata = value.get("metadata");
+        let extension = metadata
+            .and_then(|m| m.get("language_info"))
+            .and_then(|l| l.get("file_extension"))
+            .and_then(Value::as_str)
+            .map(|e| e.trim_start_matches('.').to_string())
+            .or_else(|| {
+                let language = metadata
+                    .and_then(|m| m.get("kernelspec"))
+                    .and_then(|k| k.get("language"))
+                    .and_then(Value::as_str)?;
+                Some(String::from(match language {
+                    "python" => "py",
+                    "rust" => "rs",
+                    "javascript" => "js",
+                    "typescript" => "ts",
+                    "bash" | "sh" => "sh",
+                    "c++" | "cpp" => "cpp",
+                    other => other
+                }))
+            })
+            .unwrap_or(String::from("py"));
+
+        let cells = cells
+            .iter()
+            .enumerate()
+            .map(|(idx, cell)| Cell {
+                number: idx + 1,
+                kind: cell
+                    .get("cell_type")
+                    .and_then(Value::as_str)
+                    .unwrap_or("code")
+                    .to_string(),
+                source: cell.get("source").map(text_of).unwrap_or_default(),
+                outputs: cell
+                    .get("outputs")
+                    .and_then(Value::as_array)
+                    .map(|outputs| {
+                        outputs.iter().map(output_text).collect::<Vec<_>>().join("\n")
+                    })
+                    .unwrap_or_default()
+            })
+            .collect();
+
+        Ok(Notebook { extension, cells })
+    }
+}
+
+// Notebooks store text as a string or as a list of lines that keep their
+// line endings
+fn text_of(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Array(lines) => lines.iter().filter_map(Value::as_str).collect(),
+        _ => String::new()
+    }
+}
+
+// The text of a stream, a result or an error
+fn output_text(output: &Value) -> String {
+    let mut parts: Vec<String> = Vec::new();
+    if let Some(text) = output.get("text") {
+        parts.push(text_of(text));
+    }
+    if let Some(plain) = output.get("data").and_then(|d| d.get("text/plain")) {
+        parts.push(text_of(plain));
+    }
+    if let Some(name) = output.get("ename").and_then(Value::as_str) {
+        let message = output.get("evalue").and_then(Value::as_str).unwrap_or("");
+        parts.push(format!("{}: {}", name, message));
+    }
+    parts
+        .iter()
+        .map(|p| p.trim_end_matches('\n'))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn read_cells() {
+        let text = r##"{
+            "cells": [
+                {"cell_type": "markdown", "source": ["# Title\n", "Some text"]},
+                {"cell_type": "code", "source": "x = 1\nprint(x)", "outputs": [
+                    {"output_type": "stream", "text": ["1\n"]},
+                    {"output_type": "execute_result", "data": {"text/plain": "'done'"}},
+                    {"output_type": "error", "ename": "ValueError", "evalue": "bad"}
+                ]}
+            ],
+            "metadata": {"language_info": {"name": "python", "file_extension": ".py"}}
+        }"##;
+        let notebook = match Notebook::parse(text) {
+            Ok(n) => n,
+            Err(msg) => panic!("{}", msg)
+        };
+        assert_eq!(notebook.extension, "py");
+        assert_eq!(notebook.cells.len(), 2);
+        assert_eq!(notebook.cells[0].source, "# Title\nSome text");
+        assert_eq!(notebook.cells[1].number, 2);
+        assert_eq!(notebook.cells[1].outputs, "1\n'done'\nValueError: bad");
+        assert!(Notebook::parse("{\"nbformat\": 4}").is_err());
+    }
+}
@@ -0,0 +1,215 @@
+// Support for --replace, --write and --interactive: expanding the
+// replacement text, asking about each replacement and rewriting files in
+// place.
+
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, BufRead, Write},
+    path::{Path, PathBuf}
+};
+
+
+// The answers given so far in an --interactive run. Once the user answers
+// [a]ll every remaining replacement is made, and after [q]uit none are.
+#[derive(Default)]
+pub struct Confirmation {
+    pub accept_all: bool,
+    pub quit: bool
+}
+
+impl Confirmation {
+
+    pub const fn new() -> Self {
+        Confirmation { accept_all: false, quit: false }
+    }
+
+    // Whether to make the replacement just shown. Asks on stdout and reads
+    // the answer from `input`, asking again until it gets one it knows.
+    // Running out of input counts as quitting.
+    pub fn ask(&mut self, input: &mut impl BufRead) -> bool {
+        if self.quit || self.accept_all {
+            return self.accept_all && !self.quit
+        }
+        loop {
+            print!("\x1b[1mReplace? [y]es, [n]o, [a]ll, [q]uit: \x1b[0m");
+            let _ = io::stdout().flush();
+
+            let mut answer = String::new();
+            if input.read_line(&mut answer).unwrap_or(0) == 0 {
+                self.quit = true;
+                return false
+            }
+            match answer.trim().to_lowercase().as_str() {
+                "y" | "yes" => return true,
+                "n" | "no" => return false,
+                "a" | "all" => {
+                    self.accept_all = true;
+                    return true
+                },
+                "q" | "quit" => {
+                    self.quit = true;
+                    return false
+                },
+                _ => continue
+            }
+        }
+    }
+}
+
+
+// Expands a replacement template. `$1` or `${name}` is replaced with what
+// `group` gives for that group (nothing if it has no value), `$0` is the
+// whole match and `$$` a literal '$'.
+pub fn expand(template: &str, group: impl Fn(&str) -> Option<String>) -> String {
+
+    let chars: Vec<char> = template.chars().collect();
+    let mut out = String::new();
+    let mut i: usize = 0;
+
+    while i < chars.len() {
+        if chars[i] != '$' {
+            out.push(chars[i]);
+            i += 1;
+            continue
+        }
+        let rest = &chars[i + 1..];
+        let (key, len): (String, usize) = match rest.first() {
+            Some('$') => {
+                out.push('$');
+                i += 2;
+                continue
+            },
+            Some('{') if let Some(end) = rest.iter().position(|c| *c == '}') => {
+                (rest[1..end].iter().collect(), end + 1)
+            },
+            Some(d) if d.is_ascii_digit() => {
+                let len = rest.iter().take_while(|d| d.is_ascii_digit()).count();
+                (rest[..len].iter().collect(), len)
+            },
+            _ => {
+                out.push('$');
+                i += 1;
+                continue
+            }
+        };
+        out.push_str(&group(&key).unwrap_or_default());
+        i += len + 1;
+    }
+    out
+}
+
+// The file's text with the given lines (numbered from 1) swapped for new
+// ones, or taken out along with their line ending when there's no new
+// line. Each line that stays keeps the line ending it had.
+pub fn rewrite_lines(contents: &str, new_lines: &HashMap<usize, Option<String>>) -> String {
+    let mut out = String::with_capacity(contents.len());
+    for (idx, line) in contents.split_inclusive('\n').enumerate() {
+        match new_lines.get(&(idx + 1)) {
+            Some(None) => {},
+            Some(Some(new_line)) => {
+                let ending = match line.strip_suffix('\n') {
+                    Some(l) if l.ends_with('\r') => "\r\n",
+                    Some(_) => "\n",
+                    None => ""
+                };
+                out.push_str(new_line);
+                out.push_str(ending);
+            },
+            None => out.push_str(line)
+        }
+    }
+    out
+}
+
+// Replaces the file at `path` with `contents`. They are written to a
+// temporary file next to it first, which is then renamed over the
+// original, so the file is never left half written. The original's
+// permissions are kept. A symlink is followed, so the file it points at is
+// replaced rather than the link.
+pub fn write_atomic(path: &Path, contents: &str) -> io::Result<()> {
+
+    let path = fs::canonicalize(path)?;
+    let permissions = fs::metadata(&path)?.permissions();
+    let tmp_path = temp_path(&path);
+
+    let result = fs::write(&tmp_path, contents)
+        .and_then(|_| fs::set_permissions(&tmp_path, permissions))
+        .and_then(|_| fs::rename(&tmp_path, &path));
+
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+fn temp_path(path: &Path) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{}.pscan-tmp", name))
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn expand_groups() {
+        let group = |key: &str| match key {
+            "0" => Some(String::from("TODO(dpad)")),
+            "1" | "who" => Some(String::from("dpad")),
+            _ => None
+        };
+        assert_eq!(expand("FIXME($1)", group), "FIXME(dpad)");
+        assert_eq!(expand("${who}: $0 $$5 $7", group), "dpad: TODO(dpad) $5 ");
+        assert_eq!(expand("cost $ ${open", group), "cost $ ${open");
+    }
+
+    #[test]
+    fn interactive_answers() {
+        let mut input = io::Cursor::new("y\nmaybe\nn\na\n");
+        let mut confirmation = Confirmation::new();
+        let answers: Vec<bool> = (0..4).map(|_| confirmation.ask(&mut input)).collect();
+        assert_eq!(answers, vec![true, false, true, true]);
+
+        let mut input = io::Cursor::new("q\ny\n");
+        let mut confirmation = Confirmation::new();
+        assert!(!confirmation.ask(&mut input));
+        assert!(!confirmation.ask(&mut input));
+        assert!(confirmation.quit);
+    }
+
+    #[test]
+    fn rewrite_keeps_line_endings() {
+        let contents = "one\r\ntwo\nthree";
+        let mut new_lines: HashMap<usize, Option<String>> = HashMap::new();
+        new_lines.insert(1, Some(String::from("1")));
+        new_lines.insert(3, Some(String::from("3")));
+        assert_eq!(rewrite_lines(contents, &new_lines), "1\r\ntwo\n3");
+        new_lines.insert(2, None);
+        assert_eq!(rewrite_lines(contents, &new_lines), "1\r\n3");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn write_through_symlink() {
+        let dir = std::env::temp_dir().join(format!("pscan_atomic_{}", std::process::id()));
+        let _ = fs::create_dir_all(dir.join("real"));
+        let target = dir.join("real/notes.txt");
+        let link = dir.join("notes.txt");
+        let _ = fs::write(&target, "before\n");
+        let _ = std::os::unix::fs::symlink(&target, &link);
+
+        let written = write_atomic(&link, "after\n");
+        let is_link = fs::symlink_metadata(&link).is_ok_and(|m| m.file_type().is_symlink());
+        let contents = fs::read_to_string(&target).unwrap_or_default();
+        let _ = fs::remove_dir_all(&dir);
+        assert!(written.is_ok());
+        assert!(is_link);
+        assert_eq!(contents, "after\n");
+    }
+}
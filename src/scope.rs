@@ -0,0 +1,231 @@
+// Finds the definition a line is in for --scope: the nearest enclosing
+// def/class, fn/impl, function or the like. Python scopes are followed by
+// indentation. In the brace languages a header is any line that looks like
+// a definition, and its scope lasts until the brace it opens is closed.
+// Braces and keywords in comments and strings are ignored.
+
+use crate::lexer::{Lexer, Region};
+
+
+#[derive(Clone, Copy, PartialEq)]
+enum Language {
+    Python,
+    Rust,
+    JavaScript,
+    CFamily,
+    Shell
+}
+
+// Words that start a statement rather than a definition, even when the
+// line looks like `name(...) {`
+const CONTROL: &[&str] = &[
+    "if", "else", "for", "while", "do", "switch", "case", "catch", "try",
+    "return", "with", "sizeof", "new", "throw", "await", "typeof", "elif",
+    "until", "select"
+];
+
+// Words that can come before the keyword of a definition
+const QUALIFIERS: &[&str] = &[
+    "pub", "pub(crate)", "pub(super)", "async", "unsafe", "const", "extern",
+    "\"C\"", "default", "export", "static", "public", "private", "protected",
+    "abstract", "final", "virtual", "inline", "override", "internal"
+];
+
+
+#[derive(Clone)]
+pub struct Header {
+    pub line_num: usize,
+    pub text: String,
+    level: usize    // indentation or brace depth
+}
+
+pub struct ScopeTracker {
+    language: Language,
+    lexer: Lexer,
+    stack: Vec<Header>,
+    depth: usize,
+    // A header whose opening brace hasn't been seen yet
+    pending: Option<Header>
+}
+
+impl ScopeTracker {
+
+    pub fn for_extension(ext: &str) -> Option<Self> {
+        let language = match ext {
+            "py" | "pyw" | "pyi" => Language::Python,
+            "rs" => Language::Rust,
+            "js" | "mjs" | "cjs" | "jsx" | "ts" | "mts" | "cts" | "tsx" => Language::JavaScript,
+            "sh" | "bash" | "zsh" | "ksh" => Language::Shell,
+            _ => Language::CFamily
+        };
+        Some(ScopeTracker {
+            language,
+            lexer: Lexer::for_extension(ext)?,
+            stack: Vec::new(),
+            depth: 0,
+            pending: None
+        })
+    }
+
+    // Moves on to the next line of the file, returning the header of the
+    // scope the line is in
+    pub fn advance(&mut self, line_num: usize, line: &str) -> Option<Header> {
+
+        let regions = self.lexer.regions(line);
+        let code: String = regions
+            .iter()
+            .map(|(s, e, r)| match r {
+                Region::Code => &line[*s..*e],
+                // Keep the shape of the line but none of the content
+                _ => " "
+            })
+            .collect();
+        let text = code.trim();
+
+        if self.language == Language::Python {
+            // Lines starting in a comment or string say nothing about
+            // where a block ends
+            let starts_in_code = regions
+                .iter()
+                .find(|(s, e, _)| !line[*s..*e].trim().is_empty())
+                .is_some_and(|(_, _, r)| *r == Region::Code);
+            let indent = line.len() - line.trim_start().len();
+            if !starts_in_code {
+                return self.stack.last().cloned()
+            }
+            while self.stack.last().is_some_and(|h| h.level >= indent) {
+                self.stack.pop();
+            }
+            let scope = self.stack.last().cloned();
+            if self.is_header(text) {
+                self.stack.push(Header { line_num, text: line.trim().to_string(), level: indent });
+            }
+            return scope
+        }
+
+        let scope = self.stack.last().cloned();
+        if self.is_header(text) {
+            self.pending = Some(Header { line_num, text: line.trim().to_string(), level: 0 });
+        }
+        for c in code.chars() {
+            match c {
+                '{' => {
+                    if let Some(mut header) = self.pending.take() {
+                        header.level = self.depth;
+                        self.stack.push(header);
+                    }
+                    self.depth += 1;
+                },
+                '}' => {
+                    self.depth = self.depth.saturating_sub(1);
+                    while self.stack.last().is_some_and(|h| h.level >= self.depth) {
+                        self.stack.pop();
+                    }
+                },
+                ';' => self.pending = None,
+                _ => {}
+            }
+        }
+        scope
+    }
+
+    fn is_header(&self, code: &str) -> bool {
+
+        let words: Vec<&str> = code
+            .split_whitespace()
+            .skip_while(|w| QUALIFIERS.contains(w))
+            .collect();
+        let first = words.first().copied().unwrap_or("");
+        let keyword = |k: &str| first == k || first.starts_with(&format!("{}<", k));
+
+        match self.language {
+            Language::Python => {
+                keyword("def") || keyword("class")
+            },
+            Language::Rust => {
+                ["fn", "impl", "trait", "mod"].iter().any(|k| keyword(k))
+            },
+            Language::JavaScript => {
+                first.starts_with("function")
+                    || keyword("class")
+                    || code.contains("=>") && code.contains('=') && code.ends_with('{')
+                    || looks_like_function(code)
+            },
+            Language::CFamily => {
+                ["class", "struct", "namespace", "interface", "enum", "func"]
+                    .iter()
+                    .any(|k| keyword(k))
+                    || looks_like_function(code)
+            },
+            Language::Shell => {
+                keyword("function") || code.replace(' ', "").contains("(){")
+                    || code.replace(' ', "").ends_with("()")
+            }
+        }
+    }
+}
+
+// `name(...)`, possibly with a return type before it, ending the line or
+// followed by a brace, that isn't a control statement or a call
+fn looks_like_function(code: &str) -> bool {
+    let before = match code.split_once('(') {
+        Some((b, _)) => b.trim_end(),
+        None => return false
+    };
+    let name_ok = before
+        .chars()
+        .next_back()
+        .is_some_and(|c| c.is_alphanumeric() || c == '_');
+    let first = before.split_whitespace().next().unwrap_or("");
+    let ends_ok = code.ends_with('{') || code.ends_with(')');
+    name_ok
+        && ends_ok
+        && !CONTROL.contains(&first)
+        && !before.contains(['=', '.', '!', '>', ','])
+        && !code.starts_with('}')
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    // The header line of the scope each line is in
+    fn scopes(ext: &str, source: &str) -> Vec<Option<usize>> {
+        let mut tracker = match ScopeTracker::for_extension(ext) {
+            Some(t) => t,
+            None => panic!("no scopes for .{}", ext)
+        };
+        source
+            .lines()
+            .enumerate()
+            .map(|(idx, line)| tracker.advance(idx + 1, line).map(|h| h.line_num))
+            .collect()
+    }
+
+    #[test]
+    fn python_indentation() {
+        let source = "class A:\n    def f(self):\n        x = '''\ndef g():\n'''\n\n        return x\n    y = 1\nz = 2";
+        assert_eq!(
+            scopes("py", source),
+            vec![None, Some(1), Some(2), Some(2), Some(2), Some(2), Some(2), Some(1), None]
+        );
+    }
+
+    #[test]
+    fn brace_languages() {
+        let source = "impl A {\n    pub fn f(&self)\n        -> u8 {\n        if x { \"}\" }\n    }\n    fn g();\n}\nfn h() {}";
+        assert_eq!(
+            scopes("rs", source),
+            vec![None, Some(1), Some(1), Some(2), Some(2), Some(1), Some(1), None]
+        );
+        let source = "int main(void)\n{\n    printf(\"x\");\n}";
+        assert_eq!(scopes("c", source), vec![None, None, Some(1), Some(1)]);
+        let source = "const f = (x) => {\n  g(x);\n};\nfunction h() {\n  if (a) {\n    b();\n  }\n}";
+        assert_eq!(
+            scopes("ts", source),
+            vec![None, Some(1), Some(1), None, Some(4), Some(4), Some(4), Some(4)]
+        );
+    }
+}
@@ -0,0 +1,19 @@
+# A script with print calls in code, comments and strings
+
+def greet(name):
+    print("Hello,", name)  # print the greeting
+    message = "print is not called here"
+    # print(message)
+    return message
+
+
+class Logger:
+    """Logs messages.
+
+    Call log() rather than print().
+    """
+
+    def log(self, text):
+        print(
+            text
+        )